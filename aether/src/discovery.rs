@@ -0,0 +1,56 @@
+use std::net::UdpSocket;
+
+use tracing::{debug, warn};
+use voice::discovery::{answer, parse_probe};
+
+pub use voice::DISCOVERY_PORT;
+
+/// Answers discovery probes for the aether called `name`, telling voices which `port` to connect to.
+///
+/// Voices broadcast a probe and connect to whichever aether answers first, so the nearest one wins.
+pub fn answer_probes(socket: UdpSocket, name: &str, port: u16) {
+    let mut data = [0_u8; 256];
+    loop {
+        let (size, sender) = match socket.recv_from(&mut data) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Discovery stopped: {}", e);
+                return;
+            }
+        };
+        let Ok(probe) = core::str::from_utf8(&data[..size]) else {
+            continue;
+        };
+        match parse_probe(probe) {
+            Some(wanted) if wanted == name || wanted == "*" => {
+                debug!(%sender, "Answering discovery probe");
+                if let Err(e) = socket.send_to(answer(name, port).as_bytes(), sender) {
+                    warn!(%sender, "Could not answer probe: {}", e);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn voices_find_aethers_by_name() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let probe = socket.local_addr().unwrap();
+        thread::spawn(move || answer_probes(socket, "studio", 4444));
+
+        let timeout = Duration::from_millis(200);
+        let found = voice::find_aether_at("studio", probe, timeout).unwrap();
+        assert_eq!(found, SocketAddr::from(([127, 0, 0, 1], 4444)));
+        assert_eq!(voice::find_aether_at("*", probe, timeout).unwrap(), found);
+        assert!(voice::find_aether_at("kitchen", probe, timeout).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+//! The aether, where bards' voices meet.
+//!
+//! The `aether` binary is a thin wrapper around [`Server`], which can just as well be started from inside another
+//! program or a test, either listening on a port or entirely in memory.
+
+pub mod discovery;
+pub mod limits;
+mod memory;
+pub mod metrics;
+pub mod policy;
+pub mod recording;
+mod server;
+pub mod tls;
+mod wishes;
+
+pub use limits::Limits;
+pub use memory::Pipe;
+pub use metrics::Metrics;
+pub use policy::Policy;
+pub use recording::Recorder;
+pub use server::{ANONYMOUS, Config, DEFAULT_NAMESPACE, SLOW_DOWN_AFTER, Server};
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ANONYMOUS;
+
+/// How much one bard may say.
+#[derive(Default)]
+struct Limit {
+    messages_per_second: Option<f64>,
+    bytes_per_minute: Option<f64>,
+}
+
+/// How much may still be said right away, refilled at a steady rate up to a burst.
+struct Bucket {
+    allowance: f64,
+    burst: f64,
+    per_second: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn full(burst: f64, per_second: f64, now: Instant) -> Self {
+        Bucket {
+            allowance: burst,
+            burst,
+            per_second,
+            last: now,
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now) >= self.burst
+    }
+
+    fn refill(&mut self, now: Instant) -> f64 {
+        let refilled = now.saturating_duration_since(self.last).as_secs_f64() * self.per_second;
+        self.allowance = (self.allowance + refilled).min(self.burst);
+        self.last = now;
+        self.allowance
+    }
+}
+
+/// What a bard has said lately.
+struct Usage {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl Usage {
+    /// True once the bard could say as much as if it had said nothing, so there is no need to remember it.
+    fn is_forgettable(&mut self, now: Instant) -> bool {
+        self.messages
+            .as_mut()
+            .is_none_or(|messages| messages.is_full(now))
+            && self.bytes.as_mut().is_none_or(|bytes| bytes.is_full(now))
+    }
+}
+
+/// How often bards that have been quiet long enough are forgotten.
+const PRUNE_EVERY: Duration = Duration::from_secs(60);
+
+/// Who a message counts against: a named bard in its namespace, or an anonymous connection.
+#[derive(PartialEq, Eq, Hash)]
+enum Speaker {
+    Bard(String, String),
+    Connection(String),
+}
+
+fn parse_amount(amount: &str) -> Option<f64> {
+    amount
+        .parse()
+        .ok()
+        .filter(|amount: &f64| amount.is_finite() && *amount > 0.0)
+}
+
+/// Caps how fast and how much each bard may say.
+///
+/// A limits file holds one limit per line, `#` starts a comment:
+///
+/// ```text
+/// limit typed_voice 10 messages per second
+/// limit * 1000000 bytes per minute
+/// ```
+///
+/// Amounts must be positive. `*` sets each kind of limit for every bard without that kind of its own, each bard is
+/// still counted separately in every namespace and across all its connections. Anonymous voices have nothing tying
+/// them together, so each connection is counted on its own. Messages are allowed in bursts of up to a second's worth,
+/// but always at least one, and bytes in bursts of up to a minute's worth. Bards that have been quiet long enough to
+/// be back at their full allowance are forgotten.
+pub struct Limits {
+    limits: HashMap<String, Limit>,
+    usage: Mutex<Usages>,
+}
+
+/// Everyone who has said something lately.
+struct Usages {
+    speakers: HashMap<Speaker, Usage>,
+    pruned: Option<Instant>,
+}
+
+impl Limits {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut limits: HashMap<String, Limit> = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match words[..] {
+                ["limit", bard, amount, "messages", "per", "second"] => {
+                    parse_amount(amount).map(|amount| {
+                        limits
+                            .entry(bard.to_string())
+                            .or_default()
+                            .messages_per_second = Some(amount);
+                    })
+                }
+                ["limit", bard, amount, "bytes", "per", "minute"] => {
+                    parse_amount(amount).map(|amount| {
+                        limits.entry(bard.to_string()).or_default().bytes_per_minute = Some(amount);
+                    })
+                }
+                _ => None,
+            };
+            if parsed.is_none() {
+                return Err(Error::other(format!(
+                    "limits line {} should look like `limit <bard> <n> messages per second` or `limit <bard> <n> bytes per minute` with a positive <n>: {}",
+                    number + 1,
+                    line
+                )));
+            }
+        }
+        Ok(Limits {
+            limits,
+            usage: Mutex::new(Usages {
+                speakers: HashMap::new(),
+                pruned: None,
+            }),
+        })
+    }
+
+    /// Counts a message of `bytes` from `bard` on the connection from `peer`, returning false if it goes over the
+    /// bard's limits.
+    pub fn admit(&self, peer: &str, namespace: &str, bard: &str, bytes: usize) -> bool {
+        self.admit_at(peer, namespace, bard, bytes, Instant::now())
+    }
+
+    /// Lets go of what an anonymous voice on the connection from `peer` has said, once it hangs up.
+    pub fn forget(&self, peer: &str) {
+        self.usage
+            .lock()
+            .unwrap()
+            .speakers
+            .remove(&Speaker::Connection(peer.to_string()));
+    }
+
+    fn admit_at(
+        &self,
+        peer: &str,
+        namespace: &str,
+        bard: &str,
+        bytes: usize,
+        now: Instant,
+    ) -> bool {
+        let own = self.limits.get(bard);
+        let anyone = self.limits.get("*");
+        let limit = |kind: fn(&Limit) -> Option<f64>| own.and_then(kind).or(anyone.and_then(kind));
+        let messages_per_second = limit(|limit| limit.messages_per_second);
+        let bytes_per_minute = limit(|limit| limit.bytes_per_minute);
+        if messages_per_second.is_none() && bytes_per_minute.is_none() {
+            return true;
+        }
+        let speaker = if bard == ANONYMOUS {
+            Speaker::Connection(peer.to_string())
+        } else {
+            Speaker::Bard(namespace.to_string(), bard.to_string())
+        };
+        let mut usages = self.usage.lock().unwrap();
+        // Bards are chosen by the voices, so forget the quiet ones rather than remember every name ever used.
+        if usages
+            .pruned
+            .is_none_or(|pruned| now.saturating_duration_since(pruned) >= PRUNE_EVERY)
+        {
+            usages
+                .speakers
+                .retain(|_, usage| !usage.is_forgettable(now));
+            usages.pruned = Some(now);
+        }
+        let usage = usages.speakers.entry(speaker).or_insert_with(|| Usage {
+            messages: messages_per_second.map(|rate| Bucket::full(rate.max(1.0), rate, now)),
+            bytes: bytes_per_minute.map(|quota| Bucket::full(quota, quota / 60.0, now)),
+        });
+        let bytes = bytes as f64;
+        let over_rate = usage
+            .messages
+            .as_mut()
+            .is_some_and(|messages| messages.refill(now) < 1.0);
+        let over_quota = usage
+            .bytes
+            .as_mut()
+            .is_some_and(|quota| quota.refill(now) < bytes);
+        if over_rate || over_quota {
+            return false;
+        }
+        if let Some(messages) = &mut usage.messages {
+            messages.allowance -= 1.0;
+        }
+        if let Some(quota) = &mut usage.bytes {
+            quota.allowance -= bytes;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn bards_are_held_to_their_limits() {
+        let limits = Limits::parse(
+            "limit chatty 2 messages per second\n\
+             limit slow 0.5 messages per second\n\
+             limit * 60 bytes per minute # everyone else\n",
+        )
+        .unwrap();
+        let start = Instant::now();
+        let admit = |bard, bytes, after| {
+            limits.admit_at(
+                "peer",
+                "test",
+                bard,
+                bytes,
+                start + Duration::from_secs(after),
+            )
+        };
+
+        assert!(admit("chatty", 10, 0));
+        assert!(admit("chatty", 10, 0));
+        assert!(!admit("chatty", 10, 0));
+        assert!(admit("chatty", 10, 1));
+        assert!(!admit("chatty", 100, 5));
+
+        assert!(admit("slow", 10, 0));
+        assert!(!admit("slow", 10, 1));
+        assert!(admit("slow", 10, 2));
+
+        assert!(admit("quiet", 40, 0));
+        assert!(!admit("quiet", 40, 0));
+        assert!(admit("other", 40, 0));
+        assert!(admit("quiet", 40, 20));
+
+        assert!(limits.admit_at("peer", "elsewhere", "quiet", 40, start));
+        assert!(limits.admit_at("first", "test", ANONYMOUS, 40, start));
+        assert!(limits.admit_at("second", "test", ANONYMOUS, 40, start));
+
+        assert!(Limits::parse("limit chatty lots messages per second").is_err());
+        assert!(Limits::parse("limit chatty 100 bytes").is_err());
+        for amount in ["0", "-1", "NaN", "inf"] {
+            assert!(
+                Limits::parse(&format!("limit chatty {} messages per second", amount)).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn quiet_bards_are_forgotten() {
+        let limits = Limits::parse("limit * 1 messages per second").unwrap();
+        let start = Instant::now();
+        for bard in ["first", "second", "third"] {
+            assert!(limits.admit_at("peer", "test", bard, 10, start));
+        }
+        assert_eq!(limits.usage.lock().unwrap().speakers.len(), 3);
+
+        assert!(limits.admit_at("peer", "test", "fourth", 10, start + PRUNE_EVERY));
+        assert_eq!(limits.usage.lock().unwrap().speakers.len(), 1);
+    }
+}
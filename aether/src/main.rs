@@ -1,59 +1,160 @@
-use std::io::{Read, Write};
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::env;
+use std::net::{TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
-fn handle_client(mut stream: TcpStream) {
-    let mut data = [0 as u8; 32]; // using 50 byte buffer
-    // let timeout = time::Duration::from_secs(5);
-    // let mut deadline = SystemTime::now() + timeout;
-    let mut message = String::new();
-    while match stream.read_exact(&mut data) {
-        Ok(_) => {
-            // if deadline < SystemTime::now() {
-            //     println!("Connection timed out.");
-            //     false
-            // } else {
-            stream.write(b"ok").unwrap();
-            // deadline = SystemTime::now() + timeout;
-            true
-            // }
+use aether::{
+    Config, Limits, Policy, Recorder, SLOW_DOWN_AFTER, Server, discovery, metrics, recording, tls,
+};
+use tracing::{error, info, warn};
+
+fn main() {
+    voice::init_logging();
+
+    let mut args = env::args().skip(1);
+    let mut policy = None;
+    let mut limits = None;
+    let mut address = String::from("0.0.0.0:3333");
+    let mut name = String::from("aether");
+    let mut tls_cert: Option<PathBuf> = None;
+    let mut tls_key: Option<PathBuf> = None;
+    let mut tls_client_ca: Option<PathBuf> = None;
+    let mut recorder = None;
+    let mut replay = None;
+    let mut fast = false;
+    let mut metrics_address = None;
+    let mut drain_timeout = Duration::from_secs(5);
+    let mut slow_down_after = SLOW_DOWN_AFTER;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bind" => address = args.next().expect("--bind needs an address"),
+            "--name" => name = args.next().expect("--name needs a name"),
+            "--tls-cert" => {
+                tls_cert = Some(PathBuf::from(args.next().expect("--tls-cert needs a file")))
+            }
+            "--tls-key" => {
+                tls_key = Some(PathBuf::from(args.next().expect("--tls-key needs a file")))
+            }
+            "--tls-client-ca" => {
+                tls_client_ca = Some(PathBuf::from(
+                    args.next().expect("--tls-client-ca needs a file"),
+                ))
+            }
+            "--policy" => {
+                let path = args.next().expect("--policy needs a file");
+                policy = Some(Policy::load(&path).expect("Policy file should be readable"));
+                info!("Enforcing policy from {}", path);
+            }
+            "--limits" => {
+                let path = args.next().expect("--limits needs a file");
+                limits = Some(Limits::load(&path).expect("Limits file should be readable"));
+                info!("Enforcing limits from {}", path);
+            }
+            "--record" => {
+                let path = args.next().expect("--record needs a file");
+                recorder = Some(Recorder::create(&path).expect("Session file should be writable"));
+                info!("Recording the session to {}", path);
+            }
+            "--replay" => {
+                let path = args.next().expect("--replay needs a file");
+                replay = Some(recording::load(&path).expect("Session file should be readable"));
+                info!("Replaying the session from {}", path);
+                warn!("Bards are not asked for their tokens while replaying");
+            }
+            "--fast" => fast = true,
+            "--metrics" => metrics_address = Some(args.next().expect("--metrics needs an address")),
+            "--slow-down-after" => {
+                slow_down_after = args
+                    .next()
+                    .expect("--slow-down-after needs a number of bytes")
+                    .parse()
+                    .expect("--slow-down-after should be a number");
+            }
+            "--drain-timeout" => {
+                let seconds = args
+                    .next()
+                    .expect("--drain-timeout needs a number of seconds");
+                drain_timeout = Duration::from_secs_f64(
+                    seconds.parse().expect("--drain-timeout should be a number"),
+                );
+            }
+            // A mistyped --policy or --limits must not start an aether that lets everyone say anything.
+            _ => panic!("Unknown argument {}", arg),
         }
-        Err(_) => {
-            println!(
-                "An error occurred, terminating connection with {}",
-                stream.peer_addr().unwrap()
-            );
-            stream.shutdown(Shutdown::Both).unwrap();
-            false
+    }
+
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => {
+            info!("Voices must connect over TLS");
+            Some(
+                tls::server_config(&cert, &key, tls_client_ca.as_deref())
+                    .expect("TLS certificate and key should be readable"),
+            )
         }
-    } {
-        message = message + core::str::from_utf8(&data).unwrap();
-        if data.contains(&0u8) {
-            println!("{}", message);
-            message = String::new();
+        (None, None) if tls_client_ca.is_some() => {
+            panic!("--tls-client-ca needs --tls-cert and --tls-key")
         }
+        (None, None) => None,
+        _ => panic!("--tls-cert and --tls-key must be given together"),
+    };
+    if tls.is_some() && replay.is_some() {
+        panic!("Sessions are replayed without TLS, leave out --tls-cert and --tls-key");
     }
-}
+    let server = Server::start(
+        &address,
+        Config {
+            policy,
+            limits,
+            tls,
+            recorder,
+            slow_down_after,
+            // Recorded tokens are redacted, so replayed bards cannot prove who they are.
+            skip_token_checks: replay.is_some(),
+        },
+    )
+    .unwrap();
+    info!("Server listening on {}", address);
 
-fn main() {
-    let listener = TcpListener::bind("0.0.0.0:3333").unwrap();
-    // accept connections and process them, spawning a new thread for each one
-    println!("Server listening on port 3333");
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("New connection: {}", stream.peer_addr().unwrap());
-                thread::spawn(move || {
-                    // connection succeeded
-                    handle_client(stream)
-                });
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-                /* connection failed */
-            }
+    if let Some(metrics_address) = metrics_address {
+        let listener = TcpListener::bind(&metrics_address).expect("Metrics port should be free");
+        info!("Serving metrics on http://{}/metrics", metrics_address);
+        let metrics = server.metrics();
+        thread::spawn(move || metrics::serve(listener, metrics));
+    }
+
+    let listening = server.local_addr().unwrap();
+    let port = listening.port();
+    // Only answer probes from where voices can actually reach us.
+    match UdpSocket::bind((listening.ip(), discovery::DISCOVERY_PORT)) {
+        Ok(socket) => {
+            info!(
+                "Answering to the name {} on port {}",
+                name,
+                discovery::DISCOVERY_PORT
+            );
+            thread::spawn(move || discovery::answer_probes(socket, &name, port));
         }
+        Err(e) => warn!("Voices will not be able to discover us: {}", e),
     }
-    // close the socket server
-    drop(listener);
+
+    if let Some(entries) = replay {
+        let target = server.nearby_addr().unwrap();
+        thread::spawn(move || match recording::replay(&entries, target, fast) {
+            Ok(0) => info!("Replay finished, every reply matched the recording"),
+            Ok(differences) => warn!("Replay finished, {} replies differed", differences),
+            Err(e) => error!("Replay failed: {}", e),
+        });
+    }
+
+    let (stop, stopping) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop.send(());
+    })
+    .expect("Should be able to handle SIGINT and SIGTERM");
+    let _ = stopping.recv();
+    info!("Shutting down, giving voices {:?} to finish", drain_timeout);
+    server.drain(drain_timeout);
+    info!("Goodbye");
 }
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::io::{Error, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+
+#[derive(Default)]
+struct Bytes {
+    queue: VecDeque<u8>,
+    /// Set once the writing end hangs up.
+    closed: bool,
+}
+
+/// Bytes travelling one way down a [`Pipe`].
+#[derive(Default)]
+struct Buffer {
+    bytes: Mutex<Bytes>,
+    ready: Condvar,
+}
+
+impl Buffer {
+    fn close(&self) {
+        self.bytes.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// One end of an in-memory connection to a [`Server`](crate::Server), usable anywhere a `TcpStream` would be.
+///
+/// Reads block until the other end writes something, and return nothing once it has been dropped.
+pub struct Pipe {
+    incoming: Arc<Buffer>,
+    outgoing: Arc<Buffer>,
+}
+
+/// Makes both ends of a new in-memory connection.
+pub(crate) fn pair() -> (Pipe, Pipe) {
+    let there = Arc::new(Buffer::default());
+    let back = Arc::new(Buffer::default());
+    (
+        Pipe {
+            incoming: back.clone(),
+            outgoing: there.clone(),
+        },
+        Pipe {
+            incoming: there,
+            outgoing: back,
+        },
+    )
+}
+
+impl Pipe {
+    /// Takes a whole chunk if one has arrived, without waiting for it.
+    pub(crate) fn try_take<const N: usize>(&self) -> Option<[u8; N]> {
+        let mut bytes = self.incoming.bytes.lock().unwrap();
+        if bytes.queue.len() < N {
+            return None;
+        }
+        let mut chunk = [0_u8; N];
+        for (byte, taken) in chunk.iter_mut().zip(bytes.queue.drain(..N)) {
+            *byte = taken;
+        }
+        Some(chunk)
+    }
+
+    /// How many bytes have arrived that have not been taken yet.
+    pub(crate) fn pending(&self) -> usize {
+        self.incoming.bytes.lock().unwrap().queue.len()
+    }
+
+    /// True once the other end is gone and everything it sent has been read.
+    pub(crate) fn is_finished(&self) -> bool {
+        let bytes = self.incoming.bytes.lock().unwrap();
+        bytes.closed && bytes.queue.is_empty()
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut bytes = self.incoming.bytes.lock().unwrap();
+        while bytes.queue.is_empty() && !bytes.closed {
+            bytes = self.incoming.ready.wait(bytes).unwrap();
+        }
+        let count = buf.len().min(bytes.queue.len());
+        for (byte, taken) in buf.iter_mut().zip(bytes.queue.drain(..count)) {
+            *byte = taken;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let mut bytes = self.outgoing.bytes.lock().unwrap();
+        if bytes.closed {
+            return Err(Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "The other end of the pipe is gone",
+            ));
+        }
+        bytes.queue.extend(buf);
+        self.outgoing.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        self.outgoing.close();
+        self.incoming.close();
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+/// Upper bounds, in seconds, of the buckets message handling times are sorted into.
+const HANDLING_BUCKETS: [f64; 8] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; HANDLING_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(HANDLING_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    connections_open: u64,
+    connections_total: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    /// Messages heard, keyed by namespace and bard.
+    messages: BTreeMap<(String, String), u64>,
+    /// Messages refused by the policy, keyed by namespace and bard.
+    denied: BTreeMap<(String, String), u64>,
+    /// Messages turned away for going over the bard's limits, keyed by namespace and bard.
+    over_limit: BTreeMap<(String, String), u64>,
+    /// Wishes no connected bard could fulfil, keyed by namespace and the bard that made them.
+    unfulfillable: BTreeMap<(String, String), u64>,
+    handling: Histogram,
+}
+
+/// Counters describing what the aether has been up to, readable in Prometheus' text format.
+#[derive(Default)]
+pub struct Metrics {
+    counts: Mutex<Counts>,
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn render_single(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn render_by_bard(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    counts: &BTreeMap<(String, String), u64>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for ((namespace, bard), count) in counts {
+        let _ = writeln!(
+            out,
+            "{}{{namespace=\"{}\",bard=\"{}\"}} {}",
+            name,
+            escape(namespace),
+            escape(bard),
+            count
+        );
+    }
+}
+
+impl Metrics {
+    pub fn connected(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.connections_open += 1;
+        counts.connections_total += 1;
+    }
+
+    pub fn disconnected(&self) {
+        self.counts.lock().unwrap().connections_open -= 1;
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.counts.lock().unwrap().bytes_in += bytes as u64;
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.counts.lock().unwrap().bytes_out += bytes as u64;
+    }
+
+    /// Counts a whole message from `bard`, along with how long the aether took to deal with it.
+    pub fn heard(&self, namespace: &str, bard: &str, denied: bool, handling: Duration) {
+        let mut counts = self.counts.lock().unwrap();
+        let key = (namespace.to_string(), bard.to_string());
+        if denied {
+            *counts.denied.entry(key.clone()).or_default() += 1;
+        }
+        *counts.messages.entry(key).or_default() += 1;
+        counts.handling.observe(handling.as_secs_f64());
+    }
+
+    pub fn over_limit(&self, namespace: &str, bard: &str) {
+        let key = (namespace.to_string(), bard.to_string());
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .over_limit
+            .entry(key)
+            .or_default() += 1;
+    }
+
+    pub fn unfulfillable(&self, namespace: &str, bard: &str) {
+        let key = (namespace.to_string(), bard.to_string());
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .unfulfillable
+            .entry(key)
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let counts = self.counts.lock().unwrap();
+        let mut out = String::new();
+        render_single(
+            &mut out,
+            "aether_connections",
+            "gauge",
+            "Voices currently connected.",
+            counts.connections_open,
+        );
+        render_single(
+            &mut out,
+            "aether_connections_total",
+            "counter",
+            "Voices that have connected.",
+            counts.connections_total,
+        );
+        render_single(
+            &mut out,
+            "aether_received_bytes_total",
+            "counter",
+            "Bytes heard from voices.",
+            counts.bytes_in,
+        );
+        render_single(
+            &mut out,
+            "aether_sent_bytes_total",
+            "counter",
+            "Bytes said to voices.",
+            counts.bytes_out,
+        );
+        render_by_bard(
+            &mut out,
+            "aether_messages_total",
+            "Messages heard from each bard.",
+            &counts.messages,
+        );
+        render_by_bard(
+            &mut out,
+            "aether_denied_messages_total",
+            "Messages from each bard refused by the policy.",
+            &counts.denied,
+        );
+        render_by_bard(
+            &mut out,
+            "aether_over_limit_messages_total",
+            "Messages from each bard turned away for going over its limits.",
+            &counts.over_limit,
+        );
+        render_by_bard(
+            &mut out,
+            "aether_unfulfillable_wishes_total",
+            "Wishes from each bard that no connected bard could fulfil.",
+            &counts.unfulfillable,
+        );
+
+        let name = "aether_message_handling_seconds";
+        let _ = writeln!(out, "# HELP {} Time taken to deal with a message.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bound, count) in HANDLING_BUCKETS.iter().zip(counts.handling.buckets) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            name, counts.handling.count
+        );
+        let _ = writeln!(out, "{}_sum {}", name, counts.handling.sum);
+        let _ = writeln!(out, "{}_count {}", name, counts.handling.count);
+        out
+    }
+}
+
+/// How long a scrape may take before we give up on it.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers every HTTP request on `listener` with the current metrics, each on its own thread so a client that never
+/// finishes asking cannot hold up the others.
+pub fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        thread::spawn(move || answer(stream, &metrics));
+    }
+}
+
+fn answer(mut stream: TcpStream, metrics: &Metrics) {
+    if let Err(e) = stream
+        .set_read_timeout(Some(SCRAPE_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(SCRAPE_TIMEOUT)))
+    {
+        warn!("Could not time out metrics requests: {}", e);
+    }
+    // Whatever was asked for, the metrics are the only thing we serve.
+    let _ = stream.read(&mut [0_u8; 1024]);
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        warn!("Could not send metrics: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_render_in_prometheus_format() {
+        let metrics = Metrics::default();
+        metrics.connected();
+        metrics.received(64);
+        metrics.heard("demo", "file_bard", false, Duration::from_micros(300));
+        metrics.heard("demo", "file_bard", true, Duration::from_millis(2));
+        metrics.sent(4);
+
+        let text = metrics.render();
+        assert!(text.contains("aether_connections 1\n"));
+        assert!(text.contains("aether_received_bytes_total 64\n"));
+        assert!(text.contains("aether_messages_total{namespace=\"demo\",bard=\"file_bard\"} 2\n"));
+        assert!(
+            text.contains(
+                "aether_denied_messages_total{namespace=\"demo\",bard=\"file_bard\"} 1\n"
+            )
+        );
+        assert!(text.contains("aether_message_handling_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(text.contains("aether_message_handling_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn quiet_clients_do_not_hold_up_scrapes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, Arc::default()));
+
+        let _quiet = TcpStream::connect(address).unwrap();
+        let mut scrape = TcpStream::connect(address).unwrap();
+        scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        scrape.read_to_string(&mut response).unwrap();
+        assert!(response.contains("aether_connections 0\n"));
+    }
+}
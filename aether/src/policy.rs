@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+/// One `allow <bard> <operation> <pattern>` line of a policy file.
+struct Rule {
+    bard: String,
+    operation: String,
+    pattern: Vec<String>,
+}
+
+/// Decides which bards may say what.
+///
+/// A policy file holds one rule per line, `#` starts a comment:
+///
+/// ```text
+/// token file_bard s3cret
+/// allow file_bard Claim /file/ is a file
+/// allow * Wish ...
+/// ```
+///
+/// `*` matches any bard or operation, `/name/` matches any single word and a trailing `...`
+/// matches whatever is left. Anything no rule allows is denied.
+///
+/// A bard with a `token` must give it when joining, `Join <namespace> as <bard> with token <secret>`, otherwise
+/// anyone could take its name and say what it may say.
+pub struct Policy {
+    rules: Vec<Rule>,
+    tokens: HashMap<String, String>,
+}
+
+pub(crate) fn words(text: &str) -> Vec<String> {
+    text.split_whitespace().map(str::to_string).collect()
+}
+
+/// Compares secrets without giving away how much of a guess was right through how long it took.
+fn same_secret(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn is_variable(word: &str) -> bool {
+    word.len() > 2 && word.starts_with('/') && word.ends_with('/')
+}
+
+pub(crate) fn matches(pattern: &[String], statement: &[&str]) -> bool {
+    match (pattern.first(), statement.first()) {
+        (Some(word), _) if word == "..." && pattern.len() == 1 => true,
+        (Some(word), Some(said)) if word == said || is_variable(word) => {
+            matches(&pattern[1..], &statement[1..])
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl Policy {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut rules = Vec::new();
+        let mut tokens = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["allow", bard, operation, ref pattern @ ..] if !pattern.is_empty() => {
+                    rules.push(Rule {
+                        bard: bard.to_string(),
+                        operation: operation.to_string(),
+                        pattern: pattern.iter().map(|word| word.to_string()).collect(),
+                    })
+                }
+                ["token", bard, secret] => {
+                    tokens.insert(bard.to_string(), secret.to_string());
+                }
+                _ => {
+                    return Err(Error::other(format!(
+                        "policy line {} should look like `allow <bard> <operation> <pattern>` or `token <bard> <secret>`: {}",
+                        number + 1,
+                        line
+                    )));
+                }
+            }
+        }
+        Ok(Policy { rules, tokens })
+    }
+
+    /// Checks that a voice joining as `bard` really is it, bards without a token can be joined as by anyone.
+    pub fn verifies(&self, bard: &str, token: Option<&str>) -> bool {
+        match (self.tokens.get(bard), token) {
+            (None, _) => true,
+            (Some(expected), Some(token)) => same_secret(expected, token),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Checks a whole message such as `Claim dog is cute` spoken by `bard`.
+    pub fn allows(&self, bard: &str, message: &str) -> bool {
+        let mut said = message.split_whitespace();
+        let Some(operation) = said.next() else {
+            return true;
+        };
+        let statement: Vec<&str> = said.collect();
+        self.rules.iter().any(|rule| {
+            (rule.bard == "*" || rule.bard == bard)
+                && (rule.operation == "*" || rule.operation == operation)
+                && matches(&rule.pattern, &statement)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allowed_statements_pass() {
+        let policy = Policy::parse(
+            "# file bard only talks about files\n\
+             allow file_bard Claim /file/ is a file\n\
+             allow * Wish ...\n",
+        )
+        .unwrap();
+
+        assert!(policy.allows("file_bard", "Claim on_dogs.athr is a file"));
+        assert!(!policy.allows("file_bard", "Claim dog is cute"));
+        assert!(!policy.allows("typed_voice", "Claim on_dogs.athr is a file"));
+        assert!(!policy.allows("file_bard", "Retract on_dogs.athr is a file"));
+        assert!(policy.allows("typed_voice", "Wish dog is green"));
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        assert!(Policy::parse("deny file_bard Claim ...").is_err());
+        assert!(Policy::parse("allow file_bard Claim").is_err());
+        assert!(Policy::parse("token file_bard").is_err());
+
+        let policy = Policy::parse("allow  file_bard Claim ...").unwrap();
+        assert!(policy.allows("file_bard", "Claim dog is cute"));
+    }
+
+    #[test]
+    fn bards_with_tokens_must_prove_who_they_are() {
+        let policy = Policy::parse("token file_bard s3cret\nallow * Claim ...").unwrap();
+        assert!(policy.verifies("file_bard", Some("s3cret")));
+        assert!(!policy.verifies("file_bard", Some("guess")));
+        assert!(!policy.verifies("file_bard", None));
+        assert!(policy.verifies("typed_voice", None));
+    }
+}
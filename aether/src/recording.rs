@@ -0,0 +1,216 @@
+use std::collections::hash_map::Entry as Slot;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Error, LineWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{error, warn};
+
+/// One message heard or said by the aether during a session.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Time since recording started.
+    pub at: Duration,
+    /// The connection the message travelled over.
+    pub peer: String,
+    /// The bard on the other end, as far as the aether knew at the time.
+    pub bard: String,
+    /// True for messages the aether heard, false for its replies.
+    pub heard: bool,
+    pub text: String,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+impl Entry {
+    /// Writes the entry as a tab separated line: milliseconds, peer, bard, `>` or `<` and the message.
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.at.as_millis(),
+            self.peer,
+            escape(&self.bard),
+            if self.heard { '>' } else { '<' },
+            escape(&self.text)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, '\t');
+        let at = Duration::from_millis(fields.next()?.parse().ok()?);
+        let peer = fields.next()?.to_string();
+        let bard = unescape(fields.next()?);
+        let heard = match fields.next()? {
+            ">" => true,
+            "<" => false,
+            _ => return None,
+        };
+        let text = unescape(fields.next()?);
+        Some(Entry {
+            at,
+            peer,
+            bard,
+            heard,
+            text,
+        })
+    }
+}
+
+/// Writes every message the aether hears or says to a session file.
+pub struct Recorder {
+    start: Instant,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Recorder {
+            start: Instant::now(),
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+
+    pub fn record(&self, peer: &str, bard: &str, heard: bool, text: &str) {
+        let entry = Entry {
+            at: self.start.elapsed(),
+            peer: peer.to_string(),
+            bard: bard.to_string(),
+            heard,
+            text: text.to_string(),
+        };
+        // Lines are flushed as they are written so the session survives the aether crashing.
+        if let Err(e) = self
+            .file
+            .lock()
+            .unwrap()
+            .write_all(entry.to_line().as_bytes())
+        {
+            error!("Could not record message: {}", e);
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Err(e) = self.file.lock().unwrap().flush() {
+            error!("Could not flush the session file: {}", e);
+        }
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>, Error> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(number, line)| {
+            Entry::from_line(line).ok_or_else(|| {
+                Error::other(format!("Line {} is not a recorded message", number + 1))
+            })
+        })
+        .collect()
+}
+
+/// Sends `text` the way a voice would, returning the aether's reply to the final chunk.
+///
+/// Pipelining voices are only answered once per message, so there are no replies to earlier chunks to wait for.
+fn say(stream: &mut TcpStream, text: &str, pipelined: bool) -> Result<String, Error> {
+    let mut iter = text.as_bytes().chunks_exact(32);
+    let mut reply = [0_u8; 2];
+    for chunk in iter.by_ref() {
+        stream.write_all(chunk)?;
+        if !pipelined {
+            stream.read_exact(&mut reply)?;
+        }
+    }
+    let mut last = [0_u8; 32];
+    last[..iter.remainder().len()].copy_from_slice(iter.remainder());
+    stream.write_all(&last)?;
+    stream.read_exact(&mut reply)?;
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Feeds a recorded session into the aether at `address`, one connection per recorded peer.
+///
+/// Messages are sent in the order they were recorded, waiting out the recorded gaps unless `fast` is set. Replies
+/// that differ from the recording are reported, and their number returned.
+pub fn replay(entries: &[Entry], address: SocketAddr, fast: bool) -> Result<usize, Error> {
+    let mut connections: HashMap<&str, TcpStream> = HashMap::new();
+    let mut last_reply: HashMap<&str, String> = HashMap::new();
+    let mut pipelined: HashSet<&str> = HashSet::new();
+    let mut differences = 0;
+    let start = Instant::now();
+    for entry in entries {
+        if !entry.heard {
+            // Replies are recorded straight after the message they answer.
+            if let Some(reply) = last_reply.get(entry.peer.as_str())
+                && *reply != entry.text
+            {
+                warn!(
+                    at = ?entry.at,
+                    peer = %entry.peer,
+                    "Replay differs: told {} but the recording says {}",
+                    reply,
+                    entry.text
+                );
+                differences += 1;
+            }
+            continue;
+        }
+        if !fast {
+            thread::sleep(entry.at.saturating_sub(start.elapsed()));
+        }
+        let stream = match connections.entry(&entry.peer) {
+            Slot::Occupied(stream) => stream.into_mut(),
+            Slot::Vacant(slot) => slot.insert(TcpStream::connect(address)?),
+        };
+        let reply = say(stream, &entry.text, pipelined.contains(entry.peer.as_str()))?;
+        if reply == "pl" {
+            pipelined.insert(&entry.peer);
+        }
+        last_reply.insert(&entry.peer, reply);
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_survive_the_session_file() {
+        let entry = Entry {
+            at: Duration::from_millis(1500),
+            peer: "127.0.0.1:5000".to_string(),
+            bard: "file_bard".to_string(),
+            heard: true,
+            text: "Claim\ta \\ tab\nand a newline".to_string(),
+        };
+        let line = entry.to_line();
+        assert_eq!(line.matches('\n').count(), 1);
+        assert_eq!(Entry::from_line(line.trim_end_matches('\n')), Some(entry));
+    }
+}
@@ -0,0 +1,1148 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use tracing::{Span, error, field, info, info_span, warn};
+
+use crate::limits::Limits;
+use crate::memory::{self, Pipe};
+use crate::metrics::Metrics;
+use crate::policy::Policy;
+use crate::recording::Recorder;
+use crate::tls;
+use crate::wishes::Wishes;
+
+/// Namespace used by voices that never say which one they want to join.
+pub const DEFAULT_NAMESPACE: &str = "default";
+/// Name given to voices that do not introduce themselves.
+pub const ANONYMOUS: &str = voice::ANONYMOUS;
+
+/// Reply telling a voice the aether is going away and did not hear what it started to say.
+const GOODBYE: &[u8; 2] = b"by";
+
+/// Reply to a message the aether did not hear because the bard went over its limits.
+const OVER_LIMIT: &[u8; 2] = b"lm";
+
+/// Message asking the aether to only reply once per message, so the voice can send several without waiting.
+const PIPELINE: &str = "Pipeline";
+/// Reply agreeing to [`PIPELINE`], older aethers hear it as an ordinary message and say `ok`.
+const PIPELINED: &[u8; 2] = b"pl";
+/// Reply to a pipelining voice meaning its message was heard, but it should send less at once.
+const SLOW_DOWN: &[u8; 2] = b"sl";
+
+/// How far behind a pipelining voice we let ourselves fall before asking it to slow down, in bytes.
+pub const SLOW_DOWN_AFTER: usize = 1024;
+
+/// How often connections check whether the aether is draining, so they can say goodbye.
+const DRAIN_POLL: Duration = Duration::from_millis(50);
+
+/// A `Join <namespace> [as <bard> [with token <secret>] [fulfilling <patterns>]]` handshake.
+#[derive(Debug, PartialEq)]
+struct Join<'a> {
+    namespace: &'a str,
+    bard: &'a str,
+    /// Proves the voice is `bard`, see [`Policy::verifies`].
+    token: Option<&'a str>,
+    /// Patterns of the wishes the bard can fulfil, separated by `;`.
+    fulfilling: &'a str,
+}
+
+fn parse_join(message: &str) -> Option<Join<'_>> {
+    let rest = message.strip_prefix("Join ")?.trim();
+    let (namespace, bard) = match rest.split_once(" as ") {
+        Some((namespace, bard)) => (namespace.trim(), bard.trim()),
+        None => (rest, ANONYMOUS),
+    };
+    let (bard, fulfilling) = match bard.split_once(" fulfilling ") {
+        Some((bard, fulfilling)) => (bard.trim(), fulfilling.trim()),
+        None => (bard, ""),
+    };
+    let (bard, token) = match bard.split_once(" with token ") {
+        Some((bard, token)) => (bard.trim(), Some(token.trim())),
+        None => (bard, None),
+    };
+    // The token may also come last, it must never be mistaken for part of a pattern.
+    let (fulfilling, token) = match (token, fulfilling.rsplit_once(" with token ")) {
+        (None, Some((fulfilling, token))) => (fulfilling.trim(), Some(token.trim())),
+        _ => (fulfilling, token),
+    };
+    if namespace.is_empty() || bard.is_empty() {
+        None
+    } else {
+        Some(Join {
+            namespace,
+            bard,
+            token,
+            fulfilling,
+        })
+    }
+}
+
+/// Hides the secret in a handshake, so it does not end up in session files.
+fn redact(message: &str) -> Cow<'_, str> {
+    match parse_join(message).and_then(|join| join.token) {
+        Some(token) => Cow::Owned(message.replacen(
+            &format!(" with token {}", token),
+            " with token <redacted>",
+            1,
+        )),
+        None => Cow::Borrowed(message),
+    }
+}
+
+/// Everything an aether can be configured with.
+pub struct Config {
+    /// Decides what each bard may say, everything is allowed without one.
+    pub policy: Option<Policy>,
+    /// Caps how fast and how much each bard may say.
+    pub limits: Option<Limits>,
+    /// Makes voices connect over TLS.
+    pub tls: Option<Arc<ServerConfig>>,
+    /// Writes the session to a file as it happens.
+    pub recorder: Option<Recorder>,
+    /// Pipelining voices are asked to slow down once more than this many bytes they sent are waiting to be handled.
+    pub slow_down_after: usize,
+    /// Lets voices join as any bard without a token, for replaying sessions whose tokens were redacted.
+    pub skip_token_checks: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            policy: None,
+            limits: None,
+            tls: None,
+            recorder: None,
+            slow_down_after: SLOW_DOWN_AFTER,
+            skip_token_checks: false,
+        }
+    }
+}
+
+/// The configuration plus whatever state is shared by every connection.
+struct Aether {
+    config: Config,
+    metrics: Arc<Metrics>,
+    /// Set once the aether starts shutting down, after which no new messages are heard.
+    draining: AtomicBool,
+    wishes: Wishes,
+}
+
+impl Aether {
+    fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Forgets everything about the connection from `peer` once the voice is gone.
+    fn hung_up(&self, peer: &str) {
+        self.wishes.forget(peer);
+        if let Some(limits) = &self.config.limits {
+            limits.forget(peer);
+        }
+        self.metrics.disconnected();
+    }
+
+    /// Takes in a message from `bard` on the connection from `peer`, returning the reply for the voice.
+    fn hear(&self, peer: &str, namespace: &str, bard: &str, text: &str) -> &'static [u8; 2] {
+        let start = Instant::now();
+        let within_limits = self
+            .config
+            .limits
+            .as_ref()
+            .is_none_or(|limits| limits.admit(peer, namespace, bard, text.len()));
+        if !within_limits {
+            warn!(text, "Over the bard's limits");
+            self.metrics.over_limit(namespace, bard);
+            return OVER_LIMIT;
+        }
+        let denied = self
+            .config
+            .policy
+            .as_ref()
+            .is_some_and(|policy| !policy.allows(bard, text));
+        if denied {
+            warn!(text, "Denied by policy");
+        } else {
+            info!("{}", text);
+            if let Some(wish) = text.strip_prefix("Wish ") {
+                let fulfillers = self.wishes.fulfillers(namespace, wish);
+                if fulfillers.is_empty() {
+                    warn!(wish, "No connected bard can fulfil this wish");
+                    self.metrics.unfulfillable(namespace, bard);
+                } else {
+                    info!(?fulfillers, "Wish can be fulfilled");
+                }
+            }
+        }
+        self.metrics.heard(namespace, bard, denied, start.elapsed());
+        if denied { b"no" } else { b"ok" }
+    }
+}
+
+/// What the aether knows about one voice.
+struct Connection {
+    peer: String,
+    namespace: Option<String>,
+    bard: String,
+    /// The message being assembled from chunks, only decoded once it is whole so characters may span chunks.
+    message: Vec<u8>,
+    /// Whether the voice asked to only be answered once per message.
+    pipelined: bool,
+    /// Bytes the voice sent after the chunk being handled, which are still waiting their turn.
+    backlog: usize,
+    /// The bard named by the voice's client certificate, it cannot join as anyone else.
+    certified: Option<String>,
+    span: Span,
+}
+
+impl Connection {
+    fn new(peer: String) -> Self {
+        let span = info_span!(
+            "connection",
+            %peer,
+            namespace = field::Empty,
+            bard = field::Empty
+        );
+        span.in_scope(|| info!("New connection"));
+        Connection {
+            peer,
+            namespace: None,
+            bard: ANONYMOUS.to_string(),
+            message: Vec::new(),
+            pipelined: false,
+            backlog: 0,
+            certified: None,
+            span,
+        }
+    }
+
+    /// Lets the voice into the namespace it asked for, if it can prove it is the bard it claims to be.
+    ///
+    /// Voices with a client certificate are the bard it names, anyone else may need a token from the policy.
+    fn join(&mut self, join: Join, aether: &Aether) -> &'static [u8; 2] {
+        let bard = match &self.certified {
+            Some(certified) if join.bard == ANONYMOUS => certified.as_str(),
+            _ => join.bard,
+        };
+        let proven = match &self.certified {
+            Some(certified) => certified == bard,
+            None if aether.config.skip_token_checks => true,
+            None => aether
+                .config
+                .policy
+                .as_ref()
+                .is_none_or(|policy| policy.verifies(bard, join.token)),
+        };
+        if !proven {
+            warn!("Could not prove it is {}, refusing to let it join", bard);
+            return b"no";
+        }
+        self.span.record("namespace", join.namespace);
+        self.span.record("bard", bard);
+        info!("Joined namespace {} as {}", join.namespace, bard);
+        if !join.fulfilling.is_empty() {
+            info!("Can fulfil wishes like {}", join.fulfilling);
+            aether
+                .wishes
+                .declare(&self.peer, join.namespace, bard, join.fulfilling);
+        }
+        self.namespace = Some(join.namespace.to_string());
+        self.bard = bard.to_string();
+        b"ok"
+    }
+
+    /// True between messages, when hanging up will not cut the voice off mid-sentence.
+    fn is_idle(&self) -> bool {
+        self.message.is_empty()
+    }
+
+    /// Takes in one chunk from the voice, returning the reply for it if it is owed one.
+    fn receive(&mut self, data: &[u8; 32], aether: &Aether) -> Option<&'static [u8; 2]> {
+        let _connection = self.span.clone().entered();
+        // Messages already under way are heard out, new ones are turned away.
+        if aether.is_draining() && self.is_idle() {
+            return Some(GOODBYE);
+        }
+        aether.metrics.received(data.len());
+        self.message.extend_from_slice(data);
+        if !data.contains(&0u8) {
+            if self.pipelined {
+                return None;
+            }
+            aether.metrics.sent(2);
+            return Some(b"ok");
+        }
+
+        let message = std::mem::take(&mut self.message);
+        let end = message
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        let Ok(text) = core::str::from_utf8(&message[..end]) else {
+            warn!("Message is not UTF-8, refusing to hear it");
+            aether.metrics.sent(2);
+            return Some(b"no");
+        };
+        let _message = info_span!("message", bytes = text.len()).entered();
+        if let Some(recorder) = &aether.config.recorder {
+            recorder.record(&self.peer, &self.bard, true, &redact(text));
+        }
+        let reply = match &self.namespace {
+            Some(_) if text == PIPELINE => {
+                info!("Voice is pipelining");
+                self.pipelined = true;
+                PIPELINED
+            }
+            Some(namespace) => {
+                let reply = aether.hear(&self.peer, namespace, &self.bard, text);
+                let behind = self.backlog > aether.config.slow_down_after;
+                if self.pipelined && behind && reply == b"ok" {
+                    SLOW_DOWN
+                } else {
+                    reply
+                }
+            }
+            // The first message is the handshake, older voices skip it and land in the default namespace.
+            None => match parse_join(text) {
+                Some(join) => self.join(join, aether),
+                None => {
+                    self.span.record("namespace", DEFAULT_NAMESPACE);
+                    self.namespace = Some(DEFAULT_NAMESPACE.to_string());
+                    aether.hear(&self.peer, DEFAULT_NAMESPACE, &self.bard, text)
+                }
+            },
+        };
+        if let Some(recorder) = &aether.config.recorder {
+            recorder.record(
+                &self.peer,
+                &self.bard,
+                false,
+                &String::from_utf8_lossy(reply),
+            );
+        }
+        aether.metrics.sent(reply.len());
+        Some(reply)
+    }
+}
+
+fn handle_client<S: Read + Write>(mut stream: S, mut connection: Connection, aether: &Aether) {
+    // Pipelining voices may get ahead of us, what they sent that we have not handled yet waits here.
+    let mut data = [0_u8; 4096];
+    let mut filled = 0;
+    loop {
+        let replies = match stream.read(&mut data[filled..]) {
+            Ok(0) => {
+                connection
+                    .span
+                    .in_scope(|| info!("Terminating connection: the voice hung up"));
+                break;
+            }
+            Ok(read) => {
+                filled += read;
+                let mut replies = Vec::new();
+                let mut handled = 0;
+                while let Some(chunk) = data[handled..filled].first_chunk::<32>() {
+                    handled += chunk.len();
+                    connection.backlog = filled - handled;
+                    if let Some(reply) = connection.receive(chunk, aether) {
+                        replies.push(reply);
+                        if reply == GOODBYE {
+                            break;
+                        }
+                    }
+                }
+                data.copy_within(handled..filled, 0);
+                filled -= handled;
+                replies
+            }
+            // Reads time out regularly, so quiet voices can be told when we are going away.
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if !aether.is_draining() || filled > 0 || !connection.is_idle() {
+                    continue;
+                }
+                vec![GOODBYE]
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                connection
+                    .span
+                    .in_scope(|| info!("Terminating connection: {}", e));
+                break;
+            }
+        };
+        let written = replies
+            .iter()
+            .try_for_each(|reply| stream.write_all(*reply))
+            .and_then(|_| stream.flush());
+        if let Err(e) = written {
+            connection
+                .span
+                .in_scope(|| info!("Could not reply, terminating connection: {}", e));
+            break;
+        }
+        if replies.last() == Some(&GOODBYE) {
+            connection.span.in_scope(|| info!("Said goodbye"));
+            break;
+        }
+    }
+}
+
+/// Finishes the TLS handshake, so we know who is on the other end before hearing anything from them.
+fn finish_handshake(
+    tls: &mut ServerConnection,
+    stream: &mut TcpStream,
+    aether: &Aether,
+) -> Result<(), Error> {
+    while tls.is_handshaking() {
+        match tls.complete_io(stream) {
+            Ok(_) => {}
+            // Reads time out regularly, see `accept`.
+            Err(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && !aether.is_draining() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Serves a freshly accepted connection, wrapping it in TLS when the aether is configured for it.
+fn accept(mut stream: TcpStream, peer: SocketAddr, aether: &Aether) {
+    let mut connection = Connection::new(peer.to_string());
+    if let Err(e) = stream.set_read_timeout(Some(DRAIN_POLL)) {
+        connection
+            .span
+            .in_scope(|| warn!("Voice will not hear when we shut down: {}", e));
+    }
+    match &aether.config.tls {
+        Some(tls) => match ServerConnection::new(tls.clone()) {
+            Ok(mut tls) => match finish_handshake(&mut tls, &mut stream, aether) {
+                Ok(()) => {
+                    if let Some(bard) = tls::certified_bard(&tls) {
+                        connection.span.record("bard", bard.as_str());
+                        connection.bard = bard.clone();
+                        connection.certified = Some(bard);
+                    }
+                    handle_client(StreamOwned::new(tls, stream), connection, aether)
+                }
+                Err(e) => connection
+                    .span
+                    .in_scope(|| info!("TLS handshake failed: {}", e)),
+            },
+            Err(e) => connection
+                .span
+                .in_scope(|| error!("Could not start TLS: {}", e)),
+        },
+        None => handle_client(stream, connection, aether),
+    }
+}
+
+/// An aether that voices can join, either over the network or through in-memory [`Pipe`]s.
+///
+/// Network voices are served on their own threads as soon as they speak. In-memory voices are only heard when
+/// [`Server::step`] is called, so tests can decide exactly when and in what order messages are handled.
+pub struct Server {
+    aether: Arc<Aether>,
+    address: Option<SocketAddr>,
+    accepting: Option<JoinHandle<()>>,
+    /// Handles on every open network connection, so they can be closed on shutdown.
+    streams: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>,
+    pipes: Vec<(Connection, Pipe)>,
+    next_pipe: usize,
+}
+
+impl Server {
+    fn new(config: Config) -> Self {
+        Server {
+            aether: Arc::new(Aether {
+                config,
+                metrics: Arc::default(),
+                draining: AtomicBool::new(false),
+                wishes: Wishes::default(),
+            }),
+            address: None,
+            accepting: None,
+            streams: Arc::default(),
+            pipes: Vec::new(),
+            next_pipe: 0,
+        }
+    }
+
+    /// Starts an aether listening on `address`, use port 0 to let the system pick a free one.
+    pub fn start<A: ToSocketAddrs>(address: A, config: Config) -> Result<Self, Error> {
+        let listener = TcpListener::bind(address)?;
+        let mut server = Server::new(config);
+        server.address = Some(listener.local_addr()?);
+
+        let aether = server.aether.clone();
+        let streams = server.streams.clone();
+        // Every voice is served on its own thread.
+        server.accepting = Some(thread::spawn(move || {
+            for stream in listener.incoming() {
+                if aether.is_draining() {
+                    break;
+                }
+                match stream.and_then(|stream| Ok((stream.peer_addr()?, stream))) {
+                    Ok((peer, stream)) => {
+                        if let Ok(handle) = stream.try_clone() {
+                            streams.lock().unwrap().insert(peer, handle);
+                        }
+                        let aether = aether.clone();
+                        let streams = streams.clone();
+                        thread::spawn(move || {
+                            aether.metrics.connected();
+                            // However the connection ends, `drain` must not be left waiting for it.
+                            let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                                accept(stream, peer, &aether)
+                            }));
+                            streams.lock().unwrap().remove(&peer);
+                            aether.hung_up(&peer.to_string());
+                            if served.is_err() {
+                                error!(%peer, "Connection fell over");
+                            }
+                        });
+                    }
+                    Err(e) => error!("Could not accept a voice: {}", e),
+                }
+            }
+        }));
+        Ok(server)
+    }
+
+    /// Makes an aether that only in-memory voices can join, see [`Server::connect`].
+    pub fn in_memory(config: Config) -> Self {
+        Server::new(config)
+    }
+
+    /// Where network voices can reach this aether, if it is listening at all.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.address
+    }
+
+    /// Where voices on this machine can reach this aether: over loopback when it listens everywhere, otherwise
+    /// wherever it listens.
+    pub fn nearby_addr(&self) -> Option<SocketAddr> {
+        let address = self.address?;
+        Some(match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, address.port()).into(),
+            IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, address.port()).into(),
+            _ => address,
+        })
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.aether.metrics.clone()
+    }
+
+    /// Opens an in-memory connection, whatever is written to the returned pipe is heard on the next [`Server::step`].
+    pub fn connect(&mut self) -> Pipe {
+        let (voice, aether) = memory::pair();
+        self.next_pipe += 1;
+        let connection = Connection::new(format!("memory:{}", self.next_pipe));
+        self.aether.metrics.connected();
+        self.pipes.push((connection, aether));
+        voice
+    }
+
+    /// Hears every whole chunk waiting on the in-memory connections, oldest connection first, and returns how many
+    /// were handled.
+    pub fn step(&mut self) -> usize {
+        let mut handled = 0;
+        let aether = &self.aether;
+        self.pipes.retain_mut(|(connection, pipe)| {
+            let mut open = true;
+            while let Some(chunk) = pipe.try_take::<32>() {
+                handled += 1;
+                connection.backlog = pipe.pending();
+                let Some(reply) = connection.receive(&chunk, aether) else {
+                    continue;
+                };
+                if pipe.write_all(reply).is_err() || reply == GOODBYE {
+                    open = false;
+                    break;
+                }
+            }
+            if !open || pipe.is_finished() {
+                connection.span.in_scope(|| info!("Terminating connection"));
+                aether.hung_up(&connection.peer);
+                false
+            } else {
+                true
+            }
+        });
+        handled
+    }
+
+    /// Stops accepting voices and hangs up on everyone already connected.
+    pub fn shutdown(self) {
+        self.drain(Duration::ZERO);
+    }
+
+    /// Stops accepting voices and lets those already connected finish what they are saying, for at most `timeout`.
+    ///
+    /// Voices are told the aether is going away the next time they start a message, or straight away if they are
+    /// quiet. Anyone still connected when `timeout` runs out is hung up on.
+    pub fn drain(mut self, timeout: Duration) {
+        self.stop(timeout);
+    }
+
+    fn stop(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        if self.aether.draining.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Wake the accepting thread so it notices we are stopping, it cannot be waited for otherwise.
+        let woken = self
+            .nearby_addr()
+            .is_some_and(|address| TcpStream::connect(address).is_ok());
+        if let Some(accepting) = self.accepting.take() {
+            if woken {
+                let _ = accepting.join();
+            } else {
+                warn!("Could not wake the thread accepting voices, leaving it behind");
+            }
+        }
+
+        let mut pipes = std::mem::take(&mut self.pipes);
+        for (connection, pipe) in &mut pipes {
+            if connection.is_idle() {
+                let _ = pipe.write_all(GOODBYE);
+            }
+        }
+        while Instant::now() < deadline && !self.streams.lock().unwrap().is_empty() {
+            thread::sleep(DRAIN_POLL);
+        }
+
+        for (peer, stream) in self.streams.lock().unwrap().iter() {
+            warn!(%peer, "Hanging up on a voice that did not finish in time");
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(recorder) = &self.aether.config.recorder {
+            recorder.flush();
+        }
+    }
+}
+
+/// Servers that are simply dropped hang up on everyone straight away, rather than leaving voices talking to nobody.
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop(Duration::ZERO);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::path::PathBuf;
+
+    use crate::{recording, tls};
+
+    #[test]
+    fn join_names_a_namespace() {
+        let join = |namespace, bard, token, fulfilling| {
+            Some(Join {
+                namespace,
+                bard,
+                token,
+                fulfilling,
+            })
+        };
+        assert_eq!(
+            parse_join("Join demo_table"),
+            join("demo_table", ANONYMOUS, None, "")
+        );
+        assert_eq!(
+            parse_join("Join demo_table as file_bard"),
+            join("demo_table", "file_bard", None, "")
+        );
+        assert_eq!(
+            parse_join(
+                "Join demo_table as painter with token s3cret fulfilling /x/ is green; /x/ is red"
+            ),
+            join(
+                "demo_table",
+                "painter",
+                Some("s3cret"),
+                "/x/ is green; /x/ is red"
+            )
+        );
+        assert_eq!(
+            parse_join("Join demo_table as painter fulfilling /x/ is green with token s3cret"),
+            join("demo_table", "painter", Some("s3cret"), "/x/ is green")
+        );
+        assert_eq!(parse_join("Join "), None);
+        assert_eq!(parse_join("Claim dog is cute"), None);
+    }
+
+    #[test]
+    fn bards_must_prove_who_they_are() {
+        let mut server = Server::in_memory(Config {
+            policy: Some(
+                Policy::parse("token file_bard s3cret\nallow file_bard Claim ...").unwrap(),
+            ),
+            ..Default::default()
+        });
+        let mut impostor = server.connect();
+        let mut file_bard = server.connect();
+        say(&mut impostor, "Join default as file_bard");
+        say(
+            &mut file_bard,
+            "Join default as file_bard with token s3cret",
+        );
+        say(&mut file_bard, "Claim dog is cute");
+        server.step();
+
+        assert_eq!(
+            redact("Join default as file_bard with token s3cret"),
+            "Join default as file_bard with token <redacted>"
+        );
+        assert_eq!(
+            redact("Join default as file_bard fulfilling /x/ is green with token s3cret"),
+            "Join default as file_bard fulfilling /x/ is green with token <redacted>"
+        );
+
+        let mut replies = [0_u8; 4];
+        impostor.read_exact(&mut replies[..2]).unwrap();
+        assert_eq!(&replies[..2], b"no");
+        file_bard.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"okok");
+    }
+
+    /// A file or folder in the temporary directory, removed when the test is done with it however it ends.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            Scratch(env::temp_dir().join(format!("aether-{}-{}", name, std::process::id())))
+        }
+    }
+
+    impl std::ops::Deref for Scratch {
+        type Target = PathBuf;
+
+        fn deref(&self) -> &PathBuf {
+            &self.0
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0).or_else(|_| std::fs::remove_file(&self.0));
+        }
+    }
+
+    /// Writes a certificate authority plus aether and voice certificates signed by it, returning their folder.
+    fn generate_keys(name: &str) -> Scratch {
+        use rcgen::{
+            BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        };
+
+        let dir = Scratch::new(name);
+        std::fs::create_dir_all(&*dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        for (file, names, usage) in [
+            (
+                "aether",
+                vec!["localhost".to_string()],
+                ExtendedKeyUsagePurpose::ServerAuth,
+            ),
+            ("voice", vec![], ExtendedKeyUsagePurpose::ClientAuth),
+        ] {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(names).unwrap();
+            if file == "voice" {
+                params.distinguished_name.push(DnType::CommonName, "friend");
+            }
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            std::fs::write(dir.join(format!("{}.pem", file)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}.key", file)), key.serialize_pem()).unwrap();
+        }
+        dir
+    }
+
+    fn start_tls(tls: Arc<ServerConfig>) -> (Server, String) {
+        let server = Server::start(
+            "127.0.0.1:0",
+            Config {
+                tls: Some(tls),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let address = format!("localhost:{}", server.local_addr().unwrap().port());
+        (server, address)
+    }
+
+    fn say(pipe: &mut Pipe, message: &str) {
+        let mut chunks = message.as_bytes().to_vec();
+        chunks.resize((message.len() / 32 + 1) * 32, 0);
+        pipe.write_all(&chunks).unwrap();
+    }
+
+    #[test]
+    fn voices_speak_over_tls() {
+        let keys = generate_keys("tls");
+        let tls =
+            tls::server_config(&keys.join("aether.pem"), &keys.join("aether.key"), None).unwrap();
+        let (server, address) = start_tls(tls);
+
+        let config = voice::TlsConfig::new(keys.join("ca.pem")).unwrap();
+        let mut voice = voice::Voice::builder("test")
+            .bard("tester")
+            .tls(&config)
+            .connect(&address)
+            .unwrap();
+        assert!(voice.speak("Claim dog is cute and this message spans several chunks"));
+        server.shutdown();
+    }
+
+    #[test]
+    fn unknown_voices_are_turned_away() {
+        let keys = generate_keys("mutual-tls");
+        let tls = tls::server_config(
+            &keys.join("aether.pem"),
+            &keys.join("aether.key"),
+            Some(&keys.join("ca.pem")),
+        )
+        .unwrap();
+        let (server, address) = start_tls(tls);
+
+        let stranger = voice::TlsConfig::new(keys.join("ca.pem")).unwrap();
+        assert!(
+            voice::Voice::builder("test")
+                .bard("stranger")
+                .tls(&stranger)
+                .connect(&address)
+                .is_err()
+        );
+
+        let friend = voice::TlsConfig::with_identity(
+            keys.join("ca.pem"),
+            keys.join("voice.pem"),
+            keys.join("voice.key"),
+        )
+        .unwrap();
+        assert!(
+            voice::Voice::builder("test")
+                .bard("friend")
+                .tls(&friend)
+                .connect(&address)
+                .is_ok()
+        );
+        assert!(
+            voice::Voice::builder("test")
+                .bard("impostor")
+                .tls(&friend)
+                .connect(&address)
+                .is_err()
+        );
+        server.shutdown();
+    }
+
+    #[test]
+    fn recorded_sessions_replay_into_a_fresh_aether() {
+        let session = Scratch::new("session");
+        let mut server = Server::in_memory(Config {
+            policy: Some(Policy::parse("allow * Claim ...").unwrap()),
+            recorder: Some(Recorder::create(&*session).unwrap()),
+            ..Default::default()
+        });
+        let mut pipe = server.connect();
+        for message in [
+            "Join test as tester",
+            "Claim dog is cute",
+            "Wish dog is green",
+        ] {
+            say(&mut pipe, message);
+        }
+        server.step();
+
+        let entries = recording::load(&*session).unwrap();
+        assert_eq!(entries.len(), 6);
+        assert!(entries[4].heard && entries[4].bard == "tester");
+        assert_eq!(entries[5].text, "no");
+
+        // Without the policy the wish is no longer refused, so the replay points out the difference.
+        let server = Server::start("127.0.0.1:0", Config::default()).unwrap();
+        let address = server.local_addr().unwrap();
+        assert_eq!(recording::replay(&entries, address, true).unwrap(), 1);
+        server.shutdown();
+    }
+
+    #[test]
+    fn sessions_with_tokens_replay_without_them() {
+        let policy = "token tester s3cret\nallow tester Claim ...";
+        let session = Scratch::new("tokens");
+        let mut server = Server::in_memory(Config {
+            policy: Some(Policy::parse(policy).unwrap()),
+            recorder: Some(Recorder::create(&*session).unwrap()),
+            ..Default::default()
+        });
+        let mut pipe = server.connect();
+        say(&mut pipe, "Join test as tester with token s3cret");
+        say(&mut pipe, "Claim dog is cute");
+        server.step();
+        let entries = recording::load(&*session).unwrap();
+        assert!(entries[0].text.ends_with("<redacted>"));
+
+        let server = Server::start(
+            "127.0.0.1:0",
+            Config {
+                policy: Some(Policy::parse(policy).unwrap()),
+                skip_token_checks: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let address = server.local_addr().unwrap();
+        assert_eq!(recording::replay(&entries, address, true).unwrap(), 0);
+        server.shutdown();
+    }
+
+    #[test]
+    fn voices_join_servers_on_ephemeral_ports() {
+        let server = Server::start("127.0.0.1:0", Config::default()).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut voice = voice::Voice::builder("test")
+            .bard("tester")
+            .connect(&address)
+            .unwrap();
+        assert!(voice.speak("Claim dog is cute"));
+
+        server.shutdown();
+        assert!(!voice.speak("Claim dog is still cute"));
+        assert!(
+            voice::Voice::builder("test")
+                .bard("tester")
+                .connect(&address)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn servers_listening_everywhere_are_reached_over_loopback() {
+        let server = Server::start("0.0.0.0:0", Config::default()).unwrap();
+        let nearby = server.nearby_addr().unwrap();
+        assert_eq!(nearby.ip(), Ipv4Addr::LOCALHOST);
+        assert_eq!(nearby.port(), server.local_addr().unwrap().port());
+        server.shutdown();
+    }
+
+    #[test]
+    fn draining_lets_voices_finish_their_messages() {
+        let server = Server::start("127.0.0.1:0", Config::default()).unwrap();
+        let address = server.local_addr().unwrap();
+        let mut quiet = voice::Voice::builder("test")
+            .bard("quiet")
+            .connect(&address.to_string())
+            .unwrap();
+        let mut busy = TcpStream::connect(address).unwrap();
+        let mut reply = [0_u8; 2];
+        busy.write_all(&[b'a'; 32]).unwrap();
+        busy.read_exact(&mut reply).unwrap();
+
+        let start = Instant::now();
+        let draining = thread::spawn(move || server.drain(Duration::from_secs(5)));
+        thread::sleep(DRAIN_POLL * 2);
+        busy.write_all(&[0_u8; 32]).unwrap();
+        busy.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ok");
+        busy.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, GOODBYE);
+        assert!(!quiet.speak("Claim dog is cute"));
+
+        draining.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn in_memory_servers_only_hear_when_stepped() {
+        let mut server = Server::in_memory(Config {
+            policy: Some(Policy::parse("allow * Claim ...").unwrap()),
+            ..Default::default()
+        });
+        let mut first = server.connect();
+        let mut second = server.connect();
+        say(&mut second, "Claim dog is cute");
+        say(&mut first, "Join test as tester");
+        say(&mut first, "Wish dog is green");
+        assert!(!server.metrics().render().contains("aether_messages_total{"));
+
+        assert_eq!(server.step(), 3);
+        let mut replies = [0_u8; 4];
+        first.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"okno");
+        second.read_exact(&mut replies[..2]).unwrap();
+        assert_eq!(&replies[..2], b"ok");
+
+        drop(first);
+        assert_eq!(server.step(), 0);
+        assert!(server.metrics().render().contains("aether_connections 1\n"));
+    }
+
+    #[test]
+    fn characters_may_span_chunks() {
+        let mut server = Server::in_memory(Config::default());
+        let mut pipe = server.connect();
+        let message = "Claim the dog is cute at noon, é";
+        assert!(!message.is_char_boundary(32));
+        say(&mut pipe, message);
+        let mut broken = [0_u8; 32];
+        broken[..3].copy_from_slice(&[b'C', 0xff, 0xfe]);
+        pipe.write_all(&broken).unwrap();
+
+        assert_eq!(server.step(), 3);
+        let mut replies = [0_u8; 6];
+        pipe.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"okokno");
+    }
+
+    #[test]
+    fn pipelining_voices_are_answered_once_per_message() {
+        let mut server = Server::in_memory(Config {
+            policy: Some(Policy::parse("allow * Claim ...").unwrap()),
+            ..Default::default()
+        });
+        let mut pipe = server.connect();
+        say(&mut pipe, "Join test as tester");
+        say(&mut pipe, PIPELINE);
+        let mut chunks = [0_u8; 64];
+        chunks[..39].copy_from_slice(b"Claim this message spans several chunks");
+        pipe.write_all(&chunks).unwrap();
+        say(&mut pipe, "Wish dog is green");
+        assert_eq!(server.step(), 5);
+
+        let mut replies = [0_u8; 8];
+        pipe.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"okplokno");
+        assert!(pipe.try_take::<1>().is_none());
+    }
+
+    #[test]
+    fn pipelining_voices_that_get_ahead_are_asked_to_slow_down() {
+        let mut server = Server::in_memory(Config {
+            slow_down_after: 32,
+            ..Default::default()
+        });
+        let mut pipe = server.connect();
+        say(&mut pipe, "Join test as tester");
+        say(&mut pipe, PIPELINE);
+        assert_eq!(server.step(), 2);
+        for number in 0..4 {
+            say(&mut pipe, &format!("Claim dog number {} is cute", number));
+        }
+        assert_eq!(server.step(), 4);
+
+        let mut replies = [0_u8; 12];
+        pipe.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"okplslslokok");
+    }
+
+    #[test]
+    fn pipelining_voices_slow_down_when_asked() {
+        let server = Server::start(
+            "127.0.0.1:0",
+            Config {
+                policy: Some(Policy::parse("allow * Claim ...").unwrap()),
+                slow_down_after: 0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut voice = voice::Voice::builder("test")
+            .bard("tester")
+            .connect(&address)
+            .unwrap();
+        assert!(voice.pipeline(8));
+        for number in 0..20 {
+            assert!(voice.send(&format!("Claim dog number {} is cute", number)));
+        }
+        assert!(voice.send("Wish dog is green"));
+        assert_eq!(voice.wait().unwrap(), 1);
+        assert!(voice.speak("Claim dog is still cute"));
+        server.shutdown();
+    }
+
+    #[test]
+    fn wishes_no_bard_can_fulfil_are_reported() {
+        let mut server = Server::in_memory(Config::default());
+        let mut painter = server.connect();
+        let mut wisher = server.connect();
+        say(&mut painter, "Join demo as painter fulfilling /x/ is green");
+        say(&mut wisher, "Join demo as wisher");
+        say(&mut wisher, "Wish dog is green");
+        say(&mut wisher, "Wish dog is blue");
+        server.step();
+        let unfulfillable = "aether_unfulfillable_wishes_total{namespace=\"demo\",bard=\"wisher\"}";
+        assert!(
+            server
+                .metrics()
+                .render()
+                .contains(&format!("{} 1\n", unfulfillable))
+        );
+
+        drop(painter);
+        server.step();
+        say(&mut wisher, "Wish dog is green");
+        server.step();
+        assert!(
+            server
+                .metrics()
+                .render()
+                .contains(&format!("{} 2\n", unfulfillable))
+        );
+    }
+
+    #[test]
+    fn bards_over_their_limits_are_not_heard() {
+        let mut server = Server::in_memory(Config {
+            limits: Some(Limits::parse("limit * 1 messages per second").unwrap()),
+            ..Default::default()
+        });
+        let mut pipe = server.connect();
+        say(&mut pipe, "Join test as chatty");
+        say(&mut pipe, "Claim dog is cute");
+        say(&mut pipe, "Claim dog is very cute");
+        server.step();
+
+        let mut replies = [0_u8; 6];
+        pipe.read_exact(&mut replies).unwrap();
+        assert_eq!(&replies, b"okoklm");
+    }
+
+    #[test]
+    fn voices_speak_through_pipes() {
+        let mut server = Server::in_memory(Config::default());
+        let pipe = server.connect();
+        let speaking = thread::spawn(move || {
+            let mut voice = voice::Voice::builder("test")
+                .bard("tester")
+                .over(pipe)
+                .unwrap();
+            voice.speak("Claim dog is cute")
+        });
+        while !speaking.is_finished() {
+            server.step();
+        }
+        assert!(speaking.join().unwrap());
+    }
+}
@@ -0,0 +1,57 @@
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ServerConfig, ServerConnection};
+use voice::tls::{certificates, private_key, roots};
+use x509_parser::prelude::{GeneralName, parse_x509_certificate};
+
+/// Builds the TLS settings the aether presents to voices.
+///
+/// When `client_ca` is given, voices must present a certificate signed by it, otherwise any voice that trusts
+/// our certificate may connect.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, Error> {
+    let key = private_key(key)?;
+    let builder = ServerConfig::builder();
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots(client_ca)?))
+                .build()
+                .map_err(Error::other)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certificates(cert)?, key)
+        .map_err(Error::other)?;
+    Ok(Arc::new(config))
+}
+
+/// The bard a voice's client certificate names: its common name, or failing that its first DNS name.
+pub(crate) fn certified_bard(connection: &ServerConnection) -> Option<String> {
+    let certificate = connection.peer_certificates()?.first()?;
+    let (_, certificate) = parse_x509_certificate(certificate).ok()?;
+    let common_name = certificate
+        .subject()
+        .iter_common_name()
+        .find_map(|name| name.as_str().ok());
+    if let Some(name) = common_name {
+        return Some(name.to_string());
+    }
+    certificate
+        .subject_alternative_name()
+        .ok()??
+        .value
+        .general_names
+        .iter()
+        .find_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_string()),
+            _ => None,
+        })
+}
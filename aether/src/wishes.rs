@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+
+use crate::policy::{matches, words};
+
+/// A wish pattern some connected bard said it can fulfil.
+struct Ability {
+    peer: String,
+    namespace: String,
+    bard: String,
+    pattern: Vec<String>,
+}
+
+/// Keeps track of which connected bards can fulfil which wishes.
+///
+/// Bards declare what they can do in their handshake, `Join <namespace> as <bard> fulfilling <pattern>; <pattern>`,
+/// using the same patterns as policy files. What they declared is forgotten when they disconnect.
+#[derive(Default)]
+pub(crate) struct Wishes {
+    abilities: Mutex<Vec<Ability>>,
+}
+
+impl Wishes {
+    pub(crate) fn declare(&self, peer: &str, namespace: &str, bard: &str, patterns: &str) {
+        let mut abilities = self.abilities.lock().unwrap();
+        for pattern in patterns.split(';').map(words) {
+            if !pattern.is_empty() {
+                abilities.push(Ability {
+                    peer: peer.to_string(),
+                    namespace: namespace.to_string(),
+                    bard: bard.to_string(),
+                    pattern,
+                });
+            }
+        }
+    }
+
+    pub(crate) fn forget(&self, peer: &str) {
+        self.abilities
+            .lock()
+            .unwrap()
+            .retain(|ability| ability.peer != peer);
+    }
+
+    /// Names the bards in `namespace` that can fulfil `wish`, in the order they connected.
+    pub(crate) fn fulfillers(&self, namespace: &str, wish: &str) -> Vec<String> {
+        let wish: Vec<&str> = wish.split_whitespace().collect();
+        let mut bards: Vec<String> = Vec::new();
+        for ability in self.abilities.lock().unwrap().iter() {
+            if ability.namespace == namespace
+                && matches(&ability.pattern, &wish)
+                && !bards.contains(&ability.bard)
+            {
+                bards.push(ability.bard.clone());
+            }
+        }
+        bards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wishes_are_matched_against_connected_bards() {
+        let wishes = Wishes::default();
+        wishes.declare("a", "demo", "painter", "/x/ is green; /x/ is red");
+        wishes.declare("b", "demo", "printer", "print ...");
+        wishes.declare("c", "other", "painter_2", "/x/ is green");
+
+        assert_eq!(wishes.fulfillers("demo", "dog is green"), ["painter"]);
+        assert_eq!(
+            wishes.fulfillers("demo", "print the whole page"),
+            ["printer"]
+        );
+        assert!(wishes.fulfillers("demo", "dog is blue").is_empty());
+
+        wishes.forget("a");
+        assert!(wishes.fulfillers("demo", "dog is green").is_empty());
+    }
+}
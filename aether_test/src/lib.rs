@@ -0,0 +1,162 @@
+//! Private aethers for testing bards against.
+//!
+//! Every [`TestAether`] listens on its own loopback port and records what it hears, so tests can run side by side
+//! and check what their bards said.
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aether::recording::{self, Entry};
+use aether::{Config, Recorder, Server};
+use voice::Voice;
+
+/// Namespace voices from [`TestAether::voice`] join.
+pub const NAMESPACE: &str = "test";
+
+/// How long the `eventually` assertions wait before giving up.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks `condition` until it holds or `timeout` runs out, returning whether it ever held.
+pub fn eventually<F: FnMut() -> bool>(timeout: Duration, mut condition: F) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if condition() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// An aether on a random loopback port, shut down when dropped.
+pub struct TestAether {
+    server: Option<Server>,
+    session: PathBuf,
+}
+
+impl TestAether {
+    pub fn start() -> Self {
+        Self::start_with(Config::default())
+    }
+
+    /// Starts an aether with `config`, which must not have a recorder: the session is recorded for the assertions.
+    pub fn start_with(mut config: Config) -> Self {
+        assert!(
+            config.recorder.is_none(),
+            "Test aethers record the session themselves, leave Config::recorder empty"
+        );
+        let session = env::temp_dir().join(format!(
+            "aether-test-{}-{}",
+            std::process::id(),
+            STARTED.fetch_add(1, Ordering::SeqCst)
+        ));
+        config.recorder =
+            Some(Recorder::create(&session).expect("Session file should be writable"));
+        let server = Server::start("127.0.0.1:0", config).expect("Test aether should start");
+        TestAether {
+            server: Some(server),
+            session,
+        }
+    }
+
+    /// Where voices can reach the aether, suitable for `AETHER_ADDRESS`.
+    pub fn address(&self) -> String {
+        self.server
+            .as_ref()
+            .and_then(Server::local_addr)
+            .expect("Test aether should be listening")
+            .to_string()
+    }
+
+    /// Connects a voice that introduces itself as `bard`.
+    pub fn voice(&self, bard: &str) -> Voice {
+        Voice::builder(NAMESPACE)
+            .bard(bard)
+            .connect(&self.address())
+            .expect("Voice should join the test aether")
+    }
+
+    /// Every message heard so far, handshakes included.
+    pub fn heard(&self) -> Vec<Entry> {
+        recording::load(&self.session)
+            .expect("Session file should be readable")
+            .into_iter()
+            .filter(|entry| entry.heard)
+            .collect()
+    }
+
+    /// Waits for the aether to hear `text` from any bard, panicking with everything it did hear if it never does.
+    pub fn assert_eventually_heard(&self, text: &str) {
+        if !eventually(TIMEOUT, || {
+            self.heard().iter().any(|entry| entry.text == text)
+        }) {
+            panic!(
+                "The aether never heard {:?}, only {:#?}",
+                text,
+                self.heard()
+            );
+        }
+    }
+
+    /// Like [`TestAether::assert_eventually_heard`], but `text` must come from `bard`.
+    pub fn assert_eventually_heard_from(&self, bard: &str, text: &str) {
+        let said = |entry: &Entry| entry.bard == bard && entry.text == text;
+        if !eventually(TIMEOUT, || self.heard().iter().any(said)) {
+            panic!(
+                "The aether never heard {:?} from {}, only {:#?}",
+                text,
+                bard,
+                self.heard()
+            );
+        }
+    }
+}
+
+impl Drop for TestAether {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.shutdown();
+        }
+        let _ = std::fs::remove_file(&self.session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voices_are_heard_by_their_own_aether() {
+        let first = TestAether::start();
+        let second = TestAether::start();
+        let mut voice = first.voice("tester");
+        assert!(voice.speak("Claim dog is cute"));
+
+        first.assert_eventually_heard_from("tester", "Claim dog is cute");
+        assert!(!eventually(Duration::from_millis(50), || {
+            second
+                .heard()
+                .iter()
+                .any(|entry| entry.text == "Claim dog is cute")
+        }));
+    }
+
+    #[test]
+    #[should_panic(expected = "record the session themselves")]
+    fn recorders_cannot_be_swapped_out() {
+        let session = env::temp_dir().join(format!("aether-test-recorder-{}", std::process::id()));
+        let recorder = Recorder::create(&session).unwrap();
+        let _ = std::fs::remove_file(&session);
+        TestAether::start_with(Config {
+            recorder: Some(recorder),
+            ..Default::default()
+        });
+    }
+}
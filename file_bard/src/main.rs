@@ -5,6 +5,7 @@ use std::{
     io,
     path::{Path, PathBuf},
 };
+use tracing::{debug_span, info};
 
 fn contains_loop<P: AsRef<Path>>(path: P) -> io::Result<Option<(PathBuf, PathBuf)>> {
     let path = path.as_ref();
@@ -16,23 +17,24 @@ fn contains_loop<P: AsRef<Path>>(path: P) -> io::Result<Option<(PathBuf, PathBuf
             return Ok(Some(looped_paths));
         }
     }
-    return Ok(None);
+    Ok(None)
 }
 
 fn walk_dir(dir: &PathBuf, func: fn(&DirEntry)) {
+    let _walk = debug_span!("walk", dir = %dir.display()).entered();
     for entry in fs::read_dir(dir).expect("We should be able to read this directory") {
         let entry = entry.expect("Entry should have a value");
         let path = entry.path();
 
         let metadata = fs::metadata(&path).expect("Path should have metadata");
-        if metadata.is_dir() && contains_loop(&path).unwrap_or(None) != None {
+        if metadata.is_dir() && contains_loop(&path).unwrap_or(None).is_some() {
             walk_dir(&path, func);
         }
         func(&entry);
     }
 }
 
-fn print_entry<'a>(entry: &'a DirEntry) {
+fn print_entry(entry: &DirEntry) {
     let path = entry.path();
     let metadata = fs::metadata(&path).expect("Path should have metadata");
     let last_modified = metadata
@@ -43,19 +45,22 @@ fn print_entry<'a>(entry: &'a DirEntry) {
         .as_secs();
 
     if last_modified < 24 * 3600 && metadata.is_file() {
-        println!(
-            "Last modified: {:?} seconds, is read only: {:?}, size: {:?} bytes, filename: {:?}",
+        info!(
             last_modified,
-            metadata.permissions().readonly(),
-            metadata.len(),
-            path.file_name()
+            read_only = metadata.permissions().readonly(),
+            size = metadata.len(),
+            filename = ?path
+                .file_name()
                 .ok_or("No filename")
-                .expect("Should resolve str for filename")
+                .expect("Should resolve str for filename"),
+            "Recently modified file"
         );
     }
 }
 
 fn main() {
+    voice::init_logging();
+
     let current_dir = env::current_dir().expect("We should have a working directory");
     walk_dir(&current_dir, print_entry);
 }
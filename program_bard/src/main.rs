@@ -0,0 +1,199 @@
+mod program;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tracing::{error, info, warn};
+use voice::{TlsConfig, Voice};
+
+/// How often the folder is checked for new, changed and deleted programs.
+const POLL: Duration = Duration::from_millis(500);
+
+/// A program that has been spoken into the aether.
+struct Loaded {
+    modified: SystemTime,
+    statements: Vec<String>,
+    voice: Voice,
+}
+
+/// Finds every `.athr` file in `dir`, along with when it was last changed.
+fn programs(dir: &Path) -> Result<HashMap<PathBuf, SystemTime>, Error> {
+    Ok(fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "athr")
+        })
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).ok()?.modified().ok()?;
+            Some((path, modified))
+        })
+        .collect())
+}
+
+/// Reads the tokens programs prove themselves with, one `token <bard> <secret>` per line like in the aether's policy.
+fn tokens(path: &Path) -> Result<HashMap<String, String>, Error> {
+    let mut tokens = HashMap::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["token", bard, secret] => {
+                tokens.insert(bard.to_string(), secret.to_string());
+            }
+            _ => {
+                return Err(Error::other(format!(
+                    "tokens line {} should look like `token <bard> <secret>`: {}",
+                    number + 1,
+                    line
+                )));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Speaks every statement, returning false if the aether went away and the rest of them went unheard.
+fn say_all(voice: &mut Voice, statements: &[String]) -> bool {
+    for statement in statements {
+        if !voice.speak(statement) {
+            if voice.is_lost() {
+                return false;
+            }
+            warn!(statement, "The aether did not hear this");
+        }
+    }
+    true
+}
+
+fn main() {
+    voice::init_logging();
+
+    let mut args = env::args().skip(1);
+    let mut dir = None;
+    let mut namespace = voice::DEFAULT_NAMESPACE.to_string();
+    let mut tokens = HashMap::new();
+    let mut tls_ca = None;
+    let mut tls_identity = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--namespace" => namespace = args.next().expect("--namespace needs a name"),
+            "--tokens" => {
+                let path = PathBuf::from(args.next().expect("--tokens needs a file"));
+                tokens = self::tokens(&path).expect("Tokens file should be readable");
+            }
+            "--tls-ca" => tls_ca = Some(args.next().expect("--tls-ca needs a file")),
+            "--tls-cert" => tls_identity.0 = Some(args.next().expect("--tls-cert needs a file")),
+            "--tls-key" => tls_identity.1 = Some(args.next().expect("--tls-key needs a file")),
+            _ if arg.starts_with("--") => panic!("Unknown argument {}", arg),
+            _ => dir = Some(PathBuf::from(arg)),
+        }
+    }
+    let dir =
+        dir.unwrap_or_else(|| env::current_dir().expect("We should have a working directory"));
+    info!("Running the programs in {}", dir.display());
+
+    let mut joining = Voice::builder(&namespace);
+    let tls = match (tls_ca, tls_identity) {
+        (Some(ca), (None, None)) => Some(TlsConfig::new(ca)),
+        (Some(ca), (Some(cert), Some(key))) => Some(TlsConfig::with_identity(ca, cert, key)),
+        (None, (None, None)) => None,
+        _ => panic!("--tls-cert and --tls-key must be given together, and need --tls-ca"),
+    };
+    if let Some(tls) = tls {
+        joining = joining.tls(&tls.expect("TLS certificates should be readable"));
+    }
+
+    let mut loaded: HashMap<PathBuf, Loaded> = HashMap::new();
+    loop {
+        // A folder we cannot read says nothing about the programs in it, so they stay as they are until we can.
+        let found = match programs(&dir) {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Could not read {}: {}", dir.display(), e);
+                thread::sleep(POLL);
+                continue;
+            }
+        };
+        loaded.retain(|path, program| {
+            if found.contains_key(path) {
+                return true;
+            }
+            info!(path = %path.display(), "Program deleted, retracting it");
+            let retractions = program::changes(&program.statements, &[]);
+            say_all(&mut program.voice, &retractions);
+            false
+        });
+
+        for (path, modified) in found {
+            if loaded
+                .get(&path)
+                .is_some_and(|program| program.modified == modified)
+            {
+                continue;
+            }
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    warn!(path = %path.display(), "Could not read program: {}", e);
+                    continue;
+                }
+            };
+            let statements = program::statements(&text);
+            match loaded.get_mut(&path) {
+                Some(program) => {
+                    info!(path = %path.display(), "Reloading program");
+                    let changes = program::changes(&program.statements, &statements);
+                    if say_all(&mut program.voice, &changes) {
+                        program.statements = statements;
+                        program.modified = modified;
+                    } else {
+                        // Forgetting the program has it spoken whole into whichever aether we reach next.
+                        warn!(path = %path.display(), "Lost the aether, will speak the program again");
+                        loaded.remove(&path);
+                    }
+                }
+                None => {
+                    // Each program speaks as its own bard, named after its file.
+                    let bard = path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let mut joining = joining.clone().bard(&bard);
+                    if let Some(token) = tokens.get(&bard) {
+                        joining = joining.token(token);
+                    }
+                    let mut voice = match joining.join() {
+                        Ok(voice) => voice,
+                        Err(e) => {
+                            error!(path = %path.display(), "Could not reach the aether: {}", e);
+                            continue;
+                        }
+                    };
+                    info!(path = %path.display(), "Loading program");
+                    if !say_all(&mut voice, &statements) {
+                        warn!(path = %path.display(), "Lost the aether, will try again");
+                        continue;
+                    }
+                    loaded.insert(
+                        path,
+                        Loaded {
+                            modified,
+                            statements,
+                            voice,
+                        },
+                    );
+                }
+            }
+        }
+        thread::sleep(POLL);
+    }
+}
@@ -0,0 +1,68 @@
+/// Splits a program into statements, one per line with indented lines carrying on the statement above.
+pub fn statements(text: &str) -> Vec<String> {
+    let mut statements: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        match statements.last_mut() {
+            Some(statement) if line.starts_with(char::is_whitespace) => {
+                statement.push('\n');
+                statement.push_str(line.trim_end());
+            }
+            _ => statements.push(trimmed.to_string()),
+        }
+    }
+    statements
+}
+
+/// What to say to take `statement` back.
+pub fn retraction(statement: &str) -> String {
+    match statement.strip_prefix("Claim ") {
+        Some(claim) => format!("Retract {}", claim),
+        None => format!("Retract {}", statement),
+    }
+}
+
+/// What to say to turn a program that said `old` into one that says `new`, retractions first.
+pub fn changes(old: &[String], new: &[String]) -> Vec<String> {
+    old.iter()
+        .filter(|statement| !new.contains(statement))
+        .map(|statement| retraction(statement))
+        .chain(
+            new.iter()
+                .filter(|statement| !old.contains(statement))
+                .cloned(),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloads_only_say_what_changed() {
+        let old = statements("# dogs\nClaim dog is cute\nClaim cute is green\n");
+        let new = statements(
+            "Claim dog is cute\n\nWhen /x/ is cute:\n    Claim /x/ is loved\nWish dog is green\n",
+        );
+        assert_eq!(
+            new,
+            [
+                "Claim dog is cute",
+                "When /x/ is cute:\n    Claim /x/ is loved",
+                "Wish dog is green"
+            ]
+        );
+        assert_eq!(
+            changes(&old, &new),
+            [
+                "Retract cute is green",
+                "When /x/ is cute:\n    Claim /x/ is loved",
+                "Wish dog is green"
+            ]
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::process::{Child, Command};
+
+use aether_test::TestAether;
+
+/// Stops the bard even when an assertion fails, it would otherwise run forever.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn programs_are_reloaded_when_they_change() {
+    let aether = TestAether::start();
+    let dir = std::env::temp_dir().join(format!("program-bard-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("dogs.athr");
+    fs::write(&program, "Claim dog is cute\nClaim cute is green\n").unwrap();
+
+    let _bard = Running(
+        Command::new(env!("CARGO_BIN_EXE_program_bard"))
+            .env("AETHER_ADDRESS", aether.address())
+            .arg(&dir)
+            .args(["--namespace", aether_test::NAMESPACE])
+            .spawn()
+            .unwrap(),
+    );
+    aether.assert_eventually_heard_from("dogs", "Claim dog is cute");
+    aether.assert_eventually_heard_from("dogs", "Claim cute is green");
+
+    fs::write(&program, "Claim dog is cute\nWish dog is green\n").unwrap();
+    aether.assert_eventually_heard_from("dogs", "Retract cute is green");
+    aether.assert_eventually_heard_from("dogs", "Wish dog is green");
+
+    fs::remove_file(&program).unwrap();
+    aether.assert_eventually_heard_from("dogs", "Retract dog is cute");
+    let _ = fs::remove_dir(&dir);
+}
@@ -3,6 +3,8 @@ use std::io::{self, BufRead};
 use voice::Voice;
 
 fn main() {
+    voice::init_logging();
+
     let mut v = Voice::new().unwrap();
 
     let stdin = io::stdin();
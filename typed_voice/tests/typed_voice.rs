@@ -0,0 +1,24 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use aether_test::TestAether;
+
+#[test]
+fn typed_lines_are_heard() {
+    let aether = TestAether::start();
+    let mut typed_voice = Command::new(env!("CARGO_BIN_EXE_typed_voice"))
+        .env("AETHER_ADDRESS", aether.address())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = typed_voice.stdin.take().unwrap();
+    writeln!(stdin, "Claim dog is cute").unwrap();
+    writeln!(stdin, "Wish this line is longer than a single chunk").unwrap();
+    drop(stdin);
+    assert!(typed_voice.wait().unwrap().success());
+
+    aether.assert_eventually_heard("Claim dog is cute");
+    aether.assert_eventually_heard("Wish this line is longer than a single chunk");
+}
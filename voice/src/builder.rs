@@ -0,0 +1,121 @@
+use std::io::{Error, Read, Write};
+use std::time::Duration;
+
+use tracing::info_span;
+
+use crate::{ANONYMOUS, TlsConfig, Voice, aether_address, find_aether};
+
+/// Everything a voice says about itself when it joins, see [`Voice::builder`].
+///
+/// The same builder can join any number of times, for example once per bard with [`VoiceBuilder::bard`].
+#[derive(Clone)]
+pub struct VoiceBuilder {
+    namespace: String,
+    bard: Option<String>,
+    token: Option<String>,
+    fulfilling: Vec<String>,
+    tls: Option<TlsConfig>,
+}
+
+impl VoiceBuilder {
+    pub(crate) fn new(namespace: &str) -> Self {
+        VoiceBuilder {
+            namespace: namespace.to_string(),
+            bard: None,
+            token: None,
+            fulfilling: Vec::new(),
+            tls: None,
+        }
+    }
+
+    /// Introduces us as `bard`, which the aether's policy uses to decide what we may say.
+    pub fn bard(mut self, bard: &str) -> Self {
+        self.bard = Some(bard.to_string());
+        self
+    }
+
+    /// Proves we are our bard with the `token` the aether's policy holds for it.
+    ///
+    /// Use [`VoiceBuilder::tls`] when the aether is not on this machine, otherwise the token can be overheard.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Tells the aether which wishes we can fulfil, such as `/x/ is green`.
+    pub fn fulfilling(mut self, wishes: &[&str]) -> Self {
+        self.fulfilling = wishes.iter().map(|wish| wish.to_string()).collect();
+        self
+    }
+
+    /// Talks to network aethers over TLS, so we can join from another machine without being overheard.
+    pub fn tls(mut self, tls: &TlsConfig) -> Self {
+        self.tls = Some(tls.clone());
+        self
+    }
+
+    /// Joins the aether at `address`.
+    pub fn connect(&self, address: &str) -> Result<Voice, Error> {
+        Voice::handshake(address, self.tls.as_ref(), self.greeting())
+    }
+
+    /// Joins the aether at `AETHER_ADDRESS`, or [`DEFAULT_ADDRESS`](crate::DEFAULT_ADDRESS) when it is not set.
+    pub fn join(&self) -> Result<Voice, Error> {
+        self.connect(&aether_address())
+    }
+
+    /// Finds the nearest aether called `name` on the local network and joins it.
+    ///
+    /// Over TLS, the aether's certificate must name the address it answered from.
+    pub fn discover(&self, name: &str) -> Result<Voice, Error> {
+        let address = find_aether(name, Duration::from_secs(2))?;
+        self.connect(&address.to_string())
+    }
+
+    /// Joins over an already open `channel`, such as one end of an in-memory pipe, without TLS.
+    pub fn over<C: Read + Write + Send + 'static>(&self, channel: C) -> Result<Voice, Error> {
+        Voice::greet(Box::new(channel), info_span!("voice"), self.greeting())
+    }
+
+    /// The handshake, `Join <namespace> [as <bard> [with token <secret>] [fulfilling <patterns>]]`.
+    fn greeting(&self) -> String {
+        let mut greeting = format!("Join {}", self.namespace);
+        if self.bard.is_none() && self.token.is_none() && self.fulfilling.is_empty() {
+            return greeting;
+        }
+        greeting += " as ";
+        greeting += self.bard.as_deref().unwrap_or(ANONYMOUS);
+        if let Some(token) = &self.token {
+            greeting += " with token ";
+            greeting += token;
+        }
+        if !self.fulfilling.is_empty() {
+            greeting += " fulfilling ";
+            greeting += &self.fulfilling.join("; ");
+        }
+        greeting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greetings_say_everything_asked_for() {
+        let builder = VoiceBuilder::new("demo");
+        assert_eq!(builder.greeting(), "Join demo");
+        assert_eq!(
+            builder.clone().fulfilling(&["/x/ is green"]).greeting(),
+            "Join demo as anonymous fulfilling /x/ is green"
+        );
+        assert_eq!(
+            builder
+                .bard("painter")
+                .token("s3cret")
+                .fulfilling(&["/x/ is green", "/x/ is red"])
+                .greeting(),
+            "Join demo as painter with token s3cret fulfilling /x/ is green; /x/ is red"
+        );
+    }
+}
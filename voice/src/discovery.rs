@@ -0,0 +1,97 @@
+//! Finding aethers on the local network.
+//!
+//! Voices broadcast `Where is <name>?` to [`DISCOVERY_PORT`], and aethers called `<name>` answer `<name> is at
+//! <port>`. Both ends of the conversation are here so they cannot drift apart.
+
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Port aethers listen on for discovery probes.
+pub const DISCOVERY_PORT: u16 = 3334;
+
+/// The probe looking for the aether called `name`, `*` means any.
+pub fn probe(name: &str) -> String {
+    format!("Where is {}?", name)
+}
+
+/// Works out which aether a probe is looking for.
+pub fn parse_probe(probe: &str) -> Option<&str> {
+    probe
+        .strip_prefix("Where is ")?
+        .strip_suffix('?')
+        .map(str::trim)
+}
+
+/// The answer telling voices the aether called `name` takes connections on `port`.
+pub fn answer(name: &str, port: u16) -> String {
+    format!("{} is at {}", name, port)
+}
+
+/// Works out which aether answered and on which port it takes connections.
+pub fn parse_answer(answer: &str) -> Option<(&str, u16)> {
+    let (name, port) = answer.rsplit_once(" is at ")?;
+    Some((name, port.trim().parse().ok()?))
+}
+
+/// Finds the nearest aether called `name` on the local network, `*` finds any aether.
+pub fn find_aether(name: &str, timeout: Duration) -> Result<SocketAddr, Error> {
+    find_aether_at(
+        name,
+        SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)),
+        timeout,
+    )
+}
+
+/// Like [`find_aether`] but sends the probe to `probe` instead of broadcasting it.
+pub fn find_aether_at(
+    name: &str,
+    probe: SocketAddr,
+    timeout: Duration,
+) -> Result<SocketAddr, Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(self::probe(name).as_bytes(), probe)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut data = [0_u8; 256];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("No aether called {} answered", name),
+            ));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (size, sender) = match socket.recv_from(&mut data) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        };
+        // The first answer to arrive came from the nearest aether.
+        let answer = core::str::from_utf8(&data[..size]).unwrap_or_default();
+        if let Some((answered, port)) = parse_answer(answer)
+            && (name == "*" || answered == name)
+        {
+            return Ok(SocketAddr::new(sender.ip(), port));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_and_answers_name_an_aether() {
+        assert_eq!(parse_probe(&probe("studio")), Some("studio"));
+        assert_eq!(parse_probe(&probe("*")), Some("*"));
+        assert_eq!(parse_probe("Where is studio"), None);
+        assert_eq!(
+            parse_answer(&answer("studio", 3333)),
+            Some(("studio", 3333))
+        );
+        assert_eq!(parse_answer("studio is at home"), None);
+    }
+}
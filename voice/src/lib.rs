@@ -1,73 +1,314 @@
+mod builder;
+pub mod discovery;
+mod logging;
+pub mod tls;
+
+use std::env;
 use std::io::{Error, Read, Write};
 use std::net::TcpStream;
-use std::str::from_utf8;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConnection, StreamOwned};
+use tracing::{Span, debug, error, info, info_span, warn};
+
+pub use builder::VoiceBuilder;
+pub use discovery::{DISCOVERY_PORT, find_aether, find_aether_at};
+pub use logging::init_logging;
+pub use tls::TlsConfig;
+
+/// Anything a voice can talk to the aether through.
+trait Channel: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Channel for T {}
+
+/// How many messages a pipelining voice may leave unanswered, see [`Voice::pipeline`].
+struct Window {
+    /// Messages sent that the aether has not answered yet.
+    unanswered: usize,
+    size: usize,
+    max: usize,
+    /// Messages the aether refused since the last [`Voice::wait`].
+    refused: usize,
+}
 
 pub struct Voice {
-    stream: TcpStream,
+    stream: Box<dyn Channel>,
+    /// Everything logged while talking to the aether is grouped under this span.
+    span: Span,
+    /// Set once the aether agreed to pipelining.
+    window: Option<Window>,
+    /// Set once the aether hung up or said goodbye.
+    lost: bool,
+}
+
+/// Namespace joined by [`Voice::new`].
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Bard a voice joins as when it does not name one.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Where [`Voice::join`] looks for the aether, unless `AETHER_ADDRESS` says otherwise.
+pub const DEFAULT_ADDRESS: &str = "localhost:3333";
+
+fn aether_address() -> String {
+    env::var("AETHER_ADDRESS").unwrap_or_else(|_| DEFAULT_ADDRESS.to_string())
 }
 
 impl Voice {
     pub fn new() -> Result<Self, Error> {
-        match TcpStream::connect("localhost:3333") {
-            Ok(stream) => {
-                println!("Successfully connected to server in port 3333");
-                Ok(Voice { stream })
+        Self::join(DEFAULT_NAMESPACE)
+    }
+
+    /// Connects to the aether and joins `namespace`, voices in other namespaces will not hear us.
+    pub fn join(namespace: &str) -> Result<Self, Error> {
+        Self::builder(namespace).join()
+    }
+
+    /// Starts describing how to join `namespace`: as which bard, with what proof, and where.
+    pub fn builder(namespace: &str) -> VoiceBuilder {
+        VoiceBuilder::new(namespace)
+    }
+
+    pub(crate) fn handshake(
+        address: &str,
+        tls: Option<&TlsConfig>,
+        greeting: String,
+    ) -> Result<Self, Error> {
+        let span = info_span!("voice", aether = address);
+        let stream = TcpStream::connect(address)?;
+        info!(parent: &span, "Successfully connected to server");
+        let stream: Box<dyn Channel> = match tls {
+            Some(tls) => {
+                let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let name = ServerName::try_from(host.to_string()).map_err(Error::other)?;
+                let connection =
+                    ClientConnection::new(tls.config.clone(), name).map_err(Error::other)?;
+                Box::new(StreamOwned::new(connection, stream))
             }
-            Err(e) => Err(e),
+            None => Box::new(stream),
+        };
+        Self::greet(stream, span, greeting)
+    }
+
+    pub(crate) fn greet(
+        stream: Box<dyn Channel>,
+        span: Span,
+        greeting: String,
+    ) -> Result<Self, Error> {
+        let mut voice = Voice {
+            stream,
+            span,
+            window: None,
+            lost: false,
+        };
+        if voice.speak(&greeting) {
+            Ok(voice)
+        } else {
+            Err(Error::other("The aether did not accept our handshake"))
         }
     }
 
     fn send_chunk(&mut self, chunk: &[u8]) -> bool {
-        if chunk.len() < 32 {
-            let mut send_buf = [0 as u8; 32];
+        let sent = if chunk.len() < 32 {
+            let mut send_buf = [0_u8; 32];
             send_buf[0..chunk.len()].copy_from_slice(chunk);
-            self.stream.write(&send_buf).unwrap();
+            self.stream.write_all(&send_buf)
         } else if chunk.len() > 32 {
-            println!("Tried to send a chunk that was too big.");
+            error!("Tried to send a chunk that was too big.");
             return false;
         } else {
-            self.stream.write(chunk).unwrap();
+            self.stream.write_all(chunk)
+        };
+        if let Err(e) = sent {
+            error!("Failed to send data: {}", e);
+            self.lost = true;
+            return false;
         }
 
-        let ok = b"ok";
-
-        // println!("Said {} waiting for reply", core::str::from_utf8(chunk).unwrap());
-        let mut data = [0 as u8; 2]; // using 32 byte buffer
-
+        let mut data = [0_u8; 2];
         match self.stream.read_exact(&mut data) {
-            Ok(_) => {
-                if &data == ok {
-                    // println!("Reply is ok!");
-                } else {
-                    let text = from_utf8(&data).unwrap();
-                    println!("Unexpected reply: {}", text);
+            Ok(_) => match &data {
+                b"ok" => {}
+                b"no" => {
+                    warn!("The aether refused to hear that.");
                     return false;
                 }
-            }
+                b"lm" => {
+                    warn!("The aether says we are over our limits.");
+                    return false;
+                }
+                b"by" => {
+                    warn!("The aether is shutting down.");
+                    self.lost = true;
+                    return false;
+                }
+                _ => {
+                    error!("Unexpected reply: {}", String::from_utf8_lossy(&data));
+                    self.lost = true;
+                    return false;
+                }
+            },
             Err(e) => {
-                println!("Failed to receive data: {}", e);
+                error!("Failed to receive data: {}", e);
+                self.lost = true;
+                return false;
             }
         }
-        return true;
+        true
     }
+    /// Asks the aether to only answer once per message, so up to `window` messages can be sent before waiting for
+    /// replies. Returns false if the aether does not know how, in which case every chunk is still waited for.
+    pub fn pipeline(&mut self, window: usize) -> bool {
+        if self.window.is_some() {
+            return true;
+        }
+        let mut chunk = [0_u8; 32];
+        chunk[..8].copy_from_slice(b"Pipeline");
+        let mut reply = [0_u8; 2];
+        if let Err(e) = self
+            .stream
+            .write_all(&chunk)
+            .and_then(|_| self.stream.read_exact(&mut reply))
+        {
+            error!(parent: &self.span, "Failed to start pipelining: {}", e);
+            self.lost = true;
+            return false;
+        }
+        if &reply != b"pl" {
+            warn!(parent: &self.span, "The aether cannot pipeline, waiting for every chunk.");
+            return false;
+        }
+        self.window = Some(Window {
+            unanswered: 0,
+            size: window.max(1),
+            max: window.max(1),
+            refused: 0,
+        });
+        true
+    }
+
+    /// Reads the aether's answer to the oldest unanswered message, returns false if no answer is coming.
+    fn read_reply(&mut self) -> bool {
+        let mut reply = [0_u8; 2];
+        if let Err(e) = self.stream.read_exact(&mut reply) {
+            error!("Failed to receive data: {}", e);
+            self.lost = true;
+            return false;
+        }
+        let Some(window) = self.window.as_mut() else {
+            return false;
+        };
+        window.unanswered -= 1;
+        match &reply {
+            b"ok" => window.size = (window.size + 1).min(window.max),
+            b"sl" => {
+                window.size = (window.size / 2).max(1);
+                debug!(
+                    "The aether asked us to slow down to {} messages",
+                    window.size
+                );
+            }
+            b"no" => {
+                warn!("The aether refused to hear that.");
+                window.refused += 1;
+            }
+            b"lm" => {
+                warn!("The aether says we are over our limits.");
+                window.refused += 1;
+            }
+            b"by" => {
+                warn!("The aether is shutting down.");
+                self.lost = true;
+                return false;
+            }
+            _ => {
+                error!("Unexpected reply: {}", String::from_utf8_lossy(&reply));
+                self.lost = true;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sends `msg` without waiting for the aether to answer it, returns false if it could not be sent.
+    ///
+    /// Once the window of unanswered messages is full this waits for the oldest to be answered. Without pipelining
+    /// this is the same as [`Voice::speak`].
+    pub fn send(&mut self, msg: &str) -> bool {
+        let Some(window) = &self.window else {
+            return self.speak(msg);
+        };
+        let mut full = window.unanswered >= window.size;
+        let _message = info_span!(parent: &self.span, "message", bytes = msg.len()).entered();
+        while full {
+            if !self.read_reply() {
+                return false;
+            }
+            full = self
+                .window
+                .as_ref()
+                .is_some_and(|window| window.unanswered >= window.size);
+        }
+        debug!("{}", msg);
+        // The message is padded out to whole chunks, with at least one zero to mark where it ends.
+        let mut chunks = msg.as_bytes().to_vec();
+        chunks.resize((msg.len() / 32 + 1) * 32, 0);
+        if let Err(e) = self.stream.write_all(&chunks) {
+            error!("Failed to send data: {}", e);
+            self.lost = true;
+            return false;
+        }
+        if let Some(window) = self.window.as_mut() {
+            window.unanswered += 1;
+        }
+        true
+    }
+
+    /// Waits for everything sent so far to be answered, returning how many messages the aether refused.
+    pub fn wait(&mut self) -> Result<usize, Error> {
+        let _waiting = self.span.clone().entered();
+        while self
+            .window
+            .as_ref()
+            .is_some_and(|window| window.unanswered > 0)
+        {
+            if !self.read_reply() {
+                return Err(Error::other("The aether stopped answering"));
+            }
+        }
+        Ok(self
+            .window
+            .as_mut()
+            .map_or(0, |window| std::mem::take(&mut window.refused)))
+    }
+
+    /// True once the aether hung up or said goodbye, after which nothing we say is heard until we join again.
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    /// Says `msg` to the aether, returns false if the aether refused to hear it.
+    ///
+    /// When pipelining this also waits for everything sent before, and returns false if any of that was refused.
     pub fn speak(&mut self, msg: &str) -> bool {
+        if self.window.is_some() {
+            return self.send(msg) && self.wait().is_ok_and(|refused| refused == 0);
+        }
+        let _message = info_span!(parent: &self.span, "message", bytes = msg.len()).entered();
+        debug!("{}", msg);
         let mut iter = msg.as_bytes().chunks_exact(32);
-        loop {
-            if let Some(chunk) = iter.next() {
-                self.send_chunk(chunk);
-            } else {
-                break;
-            }
+        let mut heard = true;
+        for chunk in iter.by_ref() {
+            heard &= self.send_chunk(chunk);
         }
-        self.send_chunk(iter.remainder());
-        return true;
+        heard &= self.send_chunk(iter.remainder());
+        heard
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn it_works() {
         let result = 2 + 2;
@@ -0,0 +1,17 @@
+use std::env;
+
+use tracing_subscriber::EnvFilter;
+
+/// Sets up logging for a bard, or the aether itself.
+///
+/// `RUST_LOG` picks what gets logged, `info` by default, and `AETHER_LOG_FORMAT=json` writes one JSON object per
+/// line so logs from several bards can be merged and filtered.
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if env::var("AETHER_LOG_FORMAT").is_ok_and(|format| format == "json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
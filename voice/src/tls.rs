@@ -0,0 +1,66 @@
+//! Loading certificates and keys, for voices and the aether alike so both read them the same way.
+
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ClientConfig, RootCertStore};
+
+/// Reads every certificate in the PEM file at `path`.
+pub fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect())
+        .map_err(|e| {
+            Error::other(format!(
+                "Could not read certificates from {:?}: {}",
+                path, e
+            ))
+        })
+}
+
+/// Reads the private key in the PEM file at `path`.
+pub fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, Error> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| Error::other(format!("Could not read private key from {:?}: {}", path, e)))
+}
+
+/// Trusts every certificate authority in the PEM file at `ca`.
+pub fn roots(ca: &Path) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates(ca)? {
+        roots.add(certificate).map_err(Error::other)?;
+    }
+    Ok(roots)
+}
+
+/// How a voice checks the aether it talks to, and optionally proves who it is in return.
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub(crate) config: Arc<ClientConfig>,
+}
+
+impl TlsConfig {
+    /// Trusts any aether whose certificate is signed by the certificate authority in `ca`.
+    pub fn new<P: AsRef<Path>>(ca: P) -> Result<Self, Error> {
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots(ca.as_ref())?)
+            .with_no_client_auth();
+        Ok(TlsConfig {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Like [`TlsConfig::new`] but also presents `cert` and `key`, for aethers that only let known voices in.
+    pub fn with_identity<P: AsRef<Path>>(ca: P, cert: P, key: P) -> Result<Self, Error> {
+        let key = private_key(key.as_ref())?;
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots(ca.as_ref())?)
+            .with_client_auth_cert(certificates(cert.as_ref())?, key)
+            .map_err(Error::other)?;
+        Ok(TlsConfig {
+            config: Arc::new(config),
+        })
+    }
+}